@@ -1,39 +1,130 @@
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
 use ultraviolet::Vec3;
 
+use crate::keyframes::Keyframes;
 use crate::scene::Scene;
 
 
+/// Timeline of an animation, turning frame numbers into snapshots of the scene.
+/// The eye flies straight from `eye_from` to `eye_to` over `frames` frames unless the keyframes say otherwise.
+/// Read from a TOML file such as
+///
+/// ```toml
+/// eye_from = { x = 4, y = 1, z = 5 }
+/// eye_to = { x = 1, y = 1, z = 20 }
+/// frames = 250
+///
+/// [keyframes]
+/// fov = [{ frame = 0, value = 30 }, { frame = 250, value = 45 }]
+/// ```
+///
+/// where missing fields keep the values of the default flight.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Animation {
-    eye_from: Vec3,
-    eye_to: Vec3,
-    frames: u32,
-    device: Arc<wgpu::Device>,
-    pipeline: Arc<wgpu::ComputePipeline>,
-    queue: Arc<wgpu::Queue>,
+    pub eye_from: Vec3,
+    pub eye_to: Vec3,
+    pub frames: u32,
+    pub keyframes: Keyframes,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self::new(Vec3::new(4., 1., 5.), Vec3::new(1., 1., 20.), 250)
+    }
 }
 
 impl Animation {
-    pub fn new(
-        eye_from: Vec3, 
-        eye_to: Vec3, 
-        frames: u32, 
-        device: Arc<wgpu::Device>, 
-        pipeline: Arc<wgpu::ComputePipeline>, 
-        queue: Arc<wgpu::Queue>) -> Self {
-            return Self {
-                eye_from,
-                eye_to,
-                frames,
-                device,
-                pipeline,
-                queue,
-            }
-    }
-
-    pub fn scene_at(&self, frame_at: u32) -> Scene {
+    pub fn new(eye_from: Vec3, eye_to: Vec3, frames: u32) -> Self {
+        Self { eye_from, eye_to, frames, keyframes: Keyframes::default() }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let animation: Animation = toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+        if animation.frames == 0 {
+            return Err(format!("{}: an animation needs at least one frame", path));
+        }
+        Ok(animation)
+    }
+
+    /// The scene at `frame_at`, with moving objects carried along by their velocities before the keyframes apply.
+    pub fn scene_at(&self, frame_at: u32) -> Result<Scene, String> {
         let df = self.eye_to - self.eye_from;
         let current_eye = self.eye_from + df * (frame_at as f32 / self.frames as f32);
-        return Scene::with_eye(current_eye);
+        let mut scene = Scene::with_eye(current_eye);
+        scene.advance(frame_at as f32);
+        self.keyframes.apply(&mut scene, frame_at as f32)?;
+        Ok(scene)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyframes::{Interpolation, Keyframe};
+
+    fn flight() -> Animation {
+        Animation::new(Vec3::new(4., 1., 5.), Vec3::new(1., 1., 20.), 250)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_scene_at_follows_the_timeline() {
+        let animation = flight();
+        assert_eq!(animation.scene_at(0).unwrap().eye, Vec3::new(4., 1., 5.));
+        assert_eq!(animation.scene_at(125).unwrap().eye, Vec3::new(2.5, 1., 12.5));
+        assert_eq!(animation.scene_at(250).unwrap().eye, Vec3::new(1., 1., 20.));
+
+        let keyframes = Keyframes {
+            fov: vec![
+                Keyframe { frame: 0., value: 30., interpolation: Interpolation::Step },
+                Keyframe { frame: 100., value: 60., interpolation: Interpolation::Step },
+            ].into(),
+            ..Default::default()
+        };
+        let animation = Animation { keyframes, ..flight() };
+        assert_eq!(animation.scene_at(99).unwrap().fov, 30.);
+        assert_eq!(animation.scene_at(100).unwrap().fov, 60.);
+        assert_eq!(animation.scene_at(125).unwrap().eye, Vec3::new(2.5, 1., 12.5));
+    }
+
+    #[test]
+    fn test_scene_at_moves_objects_by_their_velocity() {
+        let animation = flight();
+        let start = animation.scene_at(0).unwrap();
+        let later = animation.scene_at(10).unwrap();
+        for (before, after) in start.balls.iter().zip(&later.balls) {
+            assert_eq!(after.center, before.center + before.velocity * 10.);
+        }
+        assert!(start.balls.iter().any(|ball| ball.velocity != Vec3::zero()));
+    }
+
+    #[test]
+    fn test_animation_round_trips_through_toml() {
+        let keyframes = Keyframes {
+            eye: vec![
+                Keyframe { frame: 0., value: Vec3::zero(), interpolation: Interpolation::CatmullRom },
+                Keyframe { frame: 10., value: Vec3::unit_z(), interpolation: Interpolation::EaseInOut },
+            ].into(),
+            ..Default::default()
+        };
+        let animation = Animation { keyframes, ..flight() };
+
+        let text = toml::to_string(&animation).unwrap();
+        assert_eq!(toml::from_str::<Animation>(&text).unwrap(), animation);
+        assert_eq!(toml::from_str::<Animation>(&toml::to_string(&flight()).unwrap()).unwrap(), flight());
+
+        // Missing fields keep the default flight
+        let animation: Animation = toml::from_str(r#"
+            frames = 100
+
+            [keyframes]
+            fov = [{ frame = 0, value = 45 }]
+        "#).unwrap();
+        assert_eq!(animation.eye_from, flight().eye_from);
+        assert_eq!(animation.frames, 100);
+        assert_eq!(animation.scene_at(50).unwrap().fov, 45.);
+        assert!(toml::from_str::<Animation>("frame = 100").is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use std::f32::consts::TAU;
+
+use serde::{Deserialize, Serialize};
+use ultraviolet::Vec3;
+
+
+/// Arc length of each segment is measured over this many chords.
+const SAMPLES_PER_SEGMENT: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "kebab-case")]
+pub enum PathShape {
+    /// Centripetal Catmull-Rom spline through the points, which neither overshoots nor forms cusps.
+    /// An open spline starts at the first point and ends at the last one.
+    Spline {
+        points: Vec<Vec3>,
+        #[serde(default)]
+        closed: bool,
+    },
+    /// Circle of `radius` around `center`, `height` above it, flown around `turns` times.
+    Orbit {
+        center: Vec3,
+        radius: f32,
+        #[serde(default)]
+        height: f32,
+        #[serde(default = "one_turn")]
+        turns: f32,
+    },
+}
+
+fn one_turn() -> f32 {
+    1.
+}
+
+/// Path the camera flies along at constant speed between the `start` and `end` frames.
+/// It looks at `target`, an orbit's center without one, or else ahead along the path.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "PathFields", into = "PathFields")]
+pub struct CameraPath {
+    shape: PathShape,
+    start: f32,
+    end: f32,
+    target: Option<Vec3>,
+    /// Arc-length table of a spline shape, built once with the path.
+    spline: Option<Spline>,
+}
+
+/// A camera path as written in the keyframes file, checked by `CameraPath::new`.
+#[derive(Serialize, Deserialize)]
+struct PathFields {
+    #[serde(flatten)]
+    shape: PathShape,
+    start: f32,
+    end: f32,
+    target: Option<Vec3>,
+}
+
+impl TryFrom<PathFields> for CameraPath {
+    type Error = String;
+
+    fn try_from(fields: PathFields) -> Result<Self, Self::Error> {
+        CameraPath::new(fields.shape, fields.start, fields.end, fields.target)
+    }
+}
+
+impl From<CameraPath> for PathFields {
+    fn from(path: CameraPath) -> Self {
+        PathFields { shape: path.shape, start: path.start, end: path.end, target: path.target }
+    }
+}
+
+impl CameraPath {
+    /// Fails unless the path ends after it starts and a spline has at least two points.
+    pub fn new(shape: PathShape, start: f32, end: f32, target: Option<Vec3>) -> Result<Self, String> {
+        // Also rejects NaN
+        if end.partial_cmp(&start) != Some(std::cmp::Ordering::Greater) {
+            return Err(format!("The camera path must end after its start at frame {}, not at {}", start, end));
+        }
+        let spline = match &shape {
+            PathShape::Spline { points, closed } => {
+                Some(Spline::new(points.clone(), *closed).ok_or("The camera path needs at least two points")?)
+            },
+            PathShape::Orbit { .. } => None,
+        };
+        Ok(CameraPath { shape, start, end, target, spline })
+    }
+
+    /// The eye and, unless there is nothing to look at, the target of the camera at `frame`.
+    pub fn camera_at(&self, frame: f32) -> (Vec3, Option<Vec3>) {
+        let progress = ((frame - self.start) / (self.end - self.start)).clamp(0., 1.);
+        match &self.shape {
+            PathShape::Orbit { center, radius, height, turns } => {
+                let angle = progress * turns * TAU;
+                let eye = *center + Vec3::new(radius * angle.cos(), *height, radius * angle.sin());
+                (eye, Some(self.target.unwrap_or(*center)))
+            },
+            PathShape::Spline { .. } => {
+                let spline = self.spline.as_ref().expect("A spline path is built with its arc-length table");
+                let distance = progress * spline.length();
+                let eye = spline.position_at(distance);
+
+                let target = self.target.or_else(|| {
+                    // Central difference, one sided at the ends of an open path
+                    let step = spline.length() / (SAMPLES_PER_SEGMENT * spline.segments()) as f32;
+                    let ahead = spline.position_at(distance + step) - spline.position_at(distance - step);
+                    (ahead.mag_sq() > 0.).then(|| eye + ahead.normalized())
+                });
+                (eye, target)
+            },
+        }
+    }
+}
+
+/// Centripetal Catmull-Rom spline reparameterised by its arc length.
+#[derive(Debug, Clone, PartialEq)]
+struct Spline {
+    points: Vec<Vec3>,
+    closed: bool,
+    /// Distance along the spline at every `1 / SAMPLES_PER_SEGMENT` of a segment.
+    lengths: Vec<f32>,
+}
+
+impl Spline {
+    fn new(points: Vec<Vec3>, closed: bool) -> Option<Self> {
+        if points.len() < 2 {
+            return None;
+        }
+        let mut spline = Spline { points, closed, lengths: vec![0.] };
+        let mut previous = spline.evaluate(0.);
+        for sample in 1..=SAMPLES_PER_SEGMENT * spline.segments() {
+            let position = spline.evaluate(sample as f32 / SAMPLES_PER_SEGMENT as f32);
+            spline.lengths.push(spline.lengths[sample - 1] + (position - previous).mag());
+            previous = position;
+        }
+        Some(spline)
+    }
+
+    fn segments(&self) -> usize {
+        if self.closed { self.points.len() } else { self.points.len() - 1 }
+    }
+
+    fn length(&self) -> f32 {
+        *self.lengths.last().unwrap()
+    }
+
+    /// Equal steps in `distance` cover equal lengths of the spline, which moves the camera at constant speed.
+    fn position_at(&self, distance: f32) -> Vec3 {
+        let distance = distance.clamp(0., self.length());
+        let sample = self.lengths.partition_point(|&length| length <= distance).clamp(1, self.lengths.len() - 1);
+        let (before, after) = (self.lengths[sample - 1], self.lengths[sample]);
+        let fraction = if after > before { (distance - before) / (after - before) } else { 0. };
+        self.evaluate((sample - 1) as f32 / SAMPLES_PER_SEGMENT as f32 + fraction / SAMPLES_PER_SEGMENT as f32)
+    }
+
+    /// Neighbours of an open spline's ends are mirrored from the other side.
+    fn point(&self, index: isize) -> Vec3 {
+        let count = self.points.len() as isize;
+        if self.closed {
+            return self.points[index.rem_euclid(count) as usize];
+        }
+        match index {
+            -1 => 2. * self.points[0] - self.points[1],
+            index if index == count => 2. * self.points[count as usize - 1] - self.points[count as usize - 2],
+            index => self.points[index as usize],
+        }
+    }
+
+    /// `parameter` runs from 0 to the number of segments, the integer part picking the segment.
+    fn evaluate(&self, parameter: f32) -> Vec3 {
+        let segment = (parameter.floor() as usize).min(self.segments() - 1);
+        let local = parameter - segment as f32;
+        let segment = segment as isize;
+        let [p0, p1, p2, p3] = [-1, 0, 1, 2].map(|offset| self.point(segment + offset));
+
+        // Barry and Goldman's pyramidal formulation, knots spaced by the square root of the chord length
+        let knot = |from: Vec3, to: Vec3| (to - from).mag().sqrt().max(1e-4);
+        let t0 = 0.;
+        let t1 = t0 + knot(p0, p1);
+        let t2 = t1 + knot(p1, p2);
+        let t3 = t2 + knot(p2, p3);
+        let t = t1 + (t2 - t1) * local;
+
+        let blend = |a: Vec3, b: Vec3, from: f32, to: f32| a * ((to - t) / (to - from)) + b * ((t - from) / (to - from));
+        let a1 = blend(p0, p1, t0, t1);
+        let a2 = blend(p1, p2, t1, t2);
+        let a3 = blend(p2, p3, t2, t3);
+        let b1 = blend(a1, a2, t0, t2);
+        let b2 = blend(a2, a3, t1, t3);
+        blend(b1, b2, t1, t2)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spline_path_has_constant_speed() {
+        // Unevenly spaced points, a parameterisation per segment would speed up on the long one,
+        // chords between frames are slightly shorter than the arcs in the bends
+        let points = vec![Vec3::zero(), Vec3::new(1., 0., 0.), Vec3::new(1., 0., 8.), Vec3::new(2., 1., 9.)];
+        let path = CameraPath::new(PathShape::Spline { points: points.clone(), closed: false }, 0., 100., None).unwrap();
+
+        let eyes: Vec<Vec3> = (0..=100).map(|frame| path.camera_at(frame as f32).0).collect();
+        assert!((eyes[0] - points[0]).mag() < 1e-4);
+        assert!((eyes[100] - points[3]).mag() < 1e-4);
+        let steps: Vec<f32> = eyes.windows(2).map(|pair| (pair[1] - pair[0]).mag()).collect();
+        let mean = steps.iter().sum::<f32>() / steps.len() as f32;
+        assert!(steps.iter().all(|step| (step - mean).abs() < 0.05 * mean));
+
+        // Without a target the camera looks along the path
+        let (eye, target) = path.camera_at(0.);
+        let direction = target.unwrap() - eye;
+        assert!((direction.mag() - 1.).abs() < 1e-4 && direction.x > 0.9);
+        assert!(CameraPath::new(PathShape::Spline { points: vec![Vec3::zero()], closed: false }, 0., 100., None).is_err());
+    }
+
+    #[test]
+    fn test_orbit_from_toml() {
+        let path: CameraPath = toml::from_str(r#"
+            shape = "orbit"
+            center = { x = 0, y = 1, z = 0 }
+            radius = 5
+            height = 2
+            start = 10
+            end = 50
+        "#).unwrap();
+
+        for frame in [0., 20., 35., 60.] {
+            let (eye, target) = path.camera_at(frame);
+            assert_eq!(target, Some(Vec3::unit_y()));
+            assert!((Vec3::new(eye.x, 0., eye.z).mag() - 5.).abs() < 1e-4);
+            assert_eq!(eye.y, 3.);
+        }
+        assert!((path.camera_at(30.).0 - Vec3::new(-5., 3., 0.)).mag() < 1e-4);
+        assert_eq!(toml::from_str::<CameraPath>(&toml::to_string(&path).unwrap()).unwrap(), path);
+
+        // A path taking no time would divide by zero
+        let empty = toml::from_str::<CameraPath>(r#"
+            shape = "orbit"
+            center = { x = 0, y = 1, z = 0 }
+            radius = 5
+            start = 10
+            end = 10
+        "#);
+        assert!(empty.is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::film::SaveOptions;
+use crate::settings::{Budget, Filter, RenderSettings, Sampler};
+
+
+pub const USAGE: &str = "\
+Usage: gpu [OPTIONS]
+
+Options:
+    --samples <N>        samples per pixel [default: 4]
+    --depth <N>          maximum number of bounces [default: 5]
+    --roulette <X>       throughput below which paths play Russian roulette, 0 disables it [default: 0]
+    --roulette-depth <N> bounces before Russian roulette starts [default: 3]
+    --passes <N>         passes accumulated into the image, each taking --samples [default: 1]
+    --time <SECONDS>     stop starting new passes after this long
+    --adaptive <X>       stop sampling pixels whose relative error drops below X, 0 disables it [default: 0]
+    --min-samples <N>    samples a pixel takes before adaptive sampling may stop it [default: 16]
+    --save-every <N>     save the image every N passes, 0 only saves the final one [default: 0]
+    --output <FILE>      image to write, .exr and .hdr keep the full range [default: output40.jpg]
+                         frames of an animation are numbered, output.jpg becomes output.0007.jpg
+    --frames <N|A:B>     frame or range of frames of the animation, A included, B excluded [default: 10]
+    --animation <FILE>   TOML file with the flight of the eye, the number of frames and the keyframes
+    --grid <FILE>        density grid of the scene's smoke, a DGRD file unless --grid-size is given
+    --grid-size <XxYxZ>  dimensions of a headerless grid of little-endian f32 values, x varying fastest
+    --gif <FILE>         also assemble the rendered frames into an animated GIF
+    --fps <N>            frame rate of the GIF [default: 25]
+    --half               write OpenEXR with 16-bit half floats
+    --denoise <LEVELS>   denoise the image guided by the first hits' normals and albedos, 5 levels work well
+    --aovs               also write depth, normal, albedo and ids of the first hits next to the image
+    --exposure <STOPS>   exposure adjustment of 8-bit output [default: 0]
+    --tonemap <NAME>     none, reinhard, aces or agx, applied to 8-bit output [default: none]
+    --seed <N>           seed of the random numbers, the same seed renders the same image [default: 0]
+    --sampler <NAME>     independent, sobol or ign [default: independent]
+    --filter <NAME>      box, tent, gaussian or mitchell [default: box]
+    --filter-radius <X>  half width of the filter in pixels [default: depends on the filter]
+    --shutter <T|A:B>    motion blur, the shutter is open from frame time A to B, T is 0:T [default: 0]
+    --help               print this message";
+
+pub struct Options {
+    pub settings: RenderSettings,
+    pub budget: Budget,
+    pub save_every: u32,
+    pub output: String,
+    pub save_options: SaveOptions,
+    /// Levels of the à-trous denoiser, `None` leaves the image noisy.
+    pub denoise_levels: Option<u32>,
+    pub frames: Range<u32>,
+    pub animation: Option<String>,
+    pub grid: Option<String>,
+    pub grid_size: Option<[u32; 3]>,
+    pub gif: Option<String>,
+    pub fps: u32,
+}
+
+impl Options {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            settings: RenderSettings::default(),
+            budget: Budget::default(),
+            save_every: 0,
+            output: String::from("output40.jpg"),
+            save_options: SaveOptions::default(),
+            denoise_levels: None,
+            frames: 10..11,
+            animation: None,
+            grid: None,
+            grid_size: None,
+            gif: None,
+            fps: 25,
+        };
+        let mut args = args.into_iter();
+        let mut filter_radius = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--samples" => options.settings.samples = parse_value(&arg, value()?)?,
+                "--depth" => options.settings.max_depth = parse_value(&arg, value()?)?,
+                "--roulette" => options.settings.roulette_threshold = parse_value(&arg, value()?)?,
+                "--roulette-depth" => options.settings.roulette_depth = parse_value(&arg, value()?)?,
+                "--passes" => options.budget.passes = parse_value(&arg, value()?)?,
+                "--time" => {
+                    let seconds = value()?;
+                    let time = Duration::try_from_secs_f32(parse_value(&arg, seconds.clone())?)
+                        .map_err(|_| format!("Invalid value {} for {}", seconds, arg))?;
+                    options.budget.time = Some(time);
+                },
+                "--adaptive" => options.settings.adaptive_threshold = parse_value(&arg, value()?)?,
+                "--min-samples" => options.settings.adaptive_min_samples = parse_value(&arg, value()?)?,
+                "--save-every" => options.save_every = parse_value(&arg, value()?)?,
+                "--output" => options.output = value()?,
+                "--half" => options.save_options.half_float = true,
+                "--frames" => options.frames = parse_frames(&arg, value()?)?,
+                "--animation" => options.animation = Some(value()?),
+                "--grid" => options.grid = Some(value()?),
+                "--grid-size" => options.grid_size = Some(parse_grid_size(&arg, value()?)?),
+                "--gif" => options.gif = Some(value()?),
+                "--fps" => options.fps = parse_value(&arg, value()?)?,
+                "--denoise" => options.denoise_levels = Some(parse_value(&arg, value()?)?),
+                "--aovs" => options.save_options.aovs = true,
+                "--exposure" => options.save_options.output_transform.exposure = parse_value(&arg, value()?)?,
+                "--tonemap" => options.save_options.output_transform.tone_mapping = parse_value(&arg, value()?)?,
+                "--seed" => options.settings.seed = parse_value(&arg, value()?)?,
+                "--sampler" => options.settings.sampling = parse_value::<Sampler>(&arg, value()?)? as u32,
+                "--filter" => {
+                    let filter: Filter = parse_value(&arg, value()?)?;
+                    options.settings.pixel_filter = filter as u32;
+                    options.settings.filter_radius = filter.default_radius();
+                },
+                "--filter-radius" => filter_radius = Some(parse_value(&arg, value()?)?),
+                "--shutter" => {
+                    (options.settings.shutter_open, options.settings.shutter_close) = parse_shutter(&arg, value()?)?;
+                },
+                "--help" => return Err(USAGE.to_string()),
+                _ => return Err(format!("Unknown option {}\n\n{}", arg, USAGE)),
+            }
+        }
+
+        if options.settings.samples == 0 {
+            return Err(String::from("`--samples` must be at least 1"));
+        }
+        if let Some(radius) = filter_radius {
+            if radius <= 0. {
+                return Err(String::from("`--filter-radius` must be positive"));
+            }
+            options.settings.filter_radius = radius;
+        }
+        if options.grid_size.is_some() && options.grid.is_none() {
+            return Err(String::from("`--grid-size` needs a `--grid` to read"));
+        }
+        Ok(options)
+    }
+}
+
+fn parse_frames(arg: &str, value: String) -> Result<Range<u32>, String> {
+    let frames = match value.split_once(':') {
+        Some((start, end)) => parse_value(arg, start.to_string())?..parse_value(arg, end.to_string())?,
+        None => {
+            let frame = parse_value(arg, value.clone())?;
+            frame..frame + 1
+        },
+    };
+    if frames.is_empty() {
+        return Err(format!("Empty range of frames {}", value));
+    }
+    Ok(frames)
+}
+
+fn parse_shutter(arg: &str, value: String) -> Result<(f32, f32), String> {
+    let (open, close) = match value.split_once(':') {
+        Some((open, close)) => (parse_value(arg, open.to_string())?, parse_value(arg, close.to_string())?),
+        None => (0., parse_value(arg, value.clone())?),
+    };
+    if close < open {
+        return Err(format!("The shutter closes before it opens in {}", value));
+    }
+    Ok((open, close))
+}
+
+fn parse_grid_size(arg: &str, value: String) -> Result<[u32; 3], String> {
+    let dimensions = value.split('x').map(|dimension| parse_value(arg, dimension.to_string())).collect::<Result<Vec<u32>, _>>()?;
+    dimensions.try_into().map_err(|_| format!("Expected three dimensions in {}", value))
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: String) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value {} for {}", value, arg))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tonemap::ToneMapping;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_path_settings() {
+        assert_eq!(parse(&[]).unwrap().settings, RenderSettings::default());
+        let options = parse(&["--samples", "16", "--depth", "8", "--roulette", "0.1"]).unwrap();
+        assert_eq!(options.settings.samples, 16);
+        assert_eq!(options.settings.max_depth, 8);
+        assert_eq!(options.settings.roulette_threshold, 0.1);
+        assert_eq!(parse(&["--roulette-depth", "6"]).unwrap().settings.roulette_depth, 6);
+    }
+
+    #[test]
+    fn test_parse_budget() {
+        let options = parse(&["--passes", "64", "--time", "1.5", "--save-every", "8"]).unwrap();
+        assert_eq!(options.budget.passes, 64);
+        assert_eq!(options.budget.time, Some(Duration::from_millis(1500)));
+        assert_eq!(options.save_every, 8);
+        assert!(parse(&["--time", "-1"]).is_err());
+        assert!(parse(&["--time", "inf"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let options = parse(&["--output", "render.exr", "--half"]).unwrap();
+        assert_eq!(options.output, "render.exr");
+        assert!(options.save_options.half_float);
+        assert!(parse(&["--aovs"]).unwrap().save_options.aovs);
+        assert_eq!(parse(&["--denoise", "5"]).unwrap().denoise_levels, Some(5));
+        assert_eq!(parse(&[]).unwrap().denoise_levels, None);
+    }
+
+    #[test]
+    fn test_parse_animation() {
+        let options = parse(&["--frames", "0:250", "--gif", "render.gif", "--fps", "30"]).unwrap();
+        assert_eq!(options.frames, 0..250);
+        assert_eq!(options.gif.as_deref(), Some("render.gif"));
+        assert_eq!(options.fps, 30);
+        assert_eq!(parse(&["--animation", "flight.toml"]).unwrap().animation.as_deref(), Some("flight.toml"));
+        assert_eq!(parse(&["--frames", "3"]).unwrap().frames, 3..4);
+        assert!(parse(&["--frames", "5:5"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_grid() {
+        let options = parse(&["--grid", "smoke.raw", "--grid-size", "64x32x16"]).unwrap();
+        assert_eq!((options.grid.as_deref(), options.grid_size), (Some("smoke.raw"), Some([64, 32, 16])));
+        assert!(parse(&["--grid", "smoke.raw", "--grid-size", "64x32"]).is_err());
+        assert!(parse(&["--grid-size", "64x32x16"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_tone_mapping() {
+        let options = parse(&["--exposure", "-1.5", "--tonemap", "agx"]).unwrap();
+        assert_eq!(options.save_options.output_transform.exposure, -1.5);
+        assert_eq!(options.save_options.output_transform.tone_mapping, ToneMapping::Agx);
+    }
+
+    #[test]
+    fn test_parse_sampling() {
+        let options = parse(&["--adaptive", "0.02", "--min-samples", "32"]).unwrap();
+        assert_eq!(options.settings.adaptive_threshold, 0.02);
+        assert_eq!(options.settings.adaptive_min_samples, 32);
+        assert_eq!(parse(&["--seed", "42"]).unwrap().settings.seed, 42);
+        assert_eq!(parse(&["--sampler", "sobol"]).unwrap().settings.sampling, Sampler::Sobol as u32);
+        assert_eq!(parse(&["--sampler", "ign"]).unwrap().settings.sampling, Sampler::Ign as u32);
+        assert!(parse(&["--sampler", "halton"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let options = parse(&["--filter-radius", "3", "--filter", "mitchell"]).unwrap();
+        assert_eq!(options.settings.pixel_filter, Filter::Mitchell as u32);
+        assert_eq!(options.settings.filter_radius, 3.);
+        assert_eq!(parse(&["--filter", "tent"]).unwrap().settings.filter_radius, 1.);
+    }
+
+    #[test]
+    fn test_parse_shutter() {
+        let options = parse(&["--shutter", "-0.25:0.25"]).unwrap();
+        assert_eq!((options.settings.shutter_open, options.settings.shutter_close), (-0.25, 0.25));
+        assert_eq!(parse(&["--shutter", "0.5"]).unwrap().settings.shutter_close, 0.5);
+        assert!(parse(&["--shutter", "1:0"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&["--samples"]).is_err());
+        assert!(parse(&["--samples", "many"]).is_err());
+        assert!(parse(&["--frobnicate"]).is_err());
+    }
+}
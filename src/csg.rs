@@ -0,0 +1,112 @@
+use std::f32::consts::PI;
+
+use bytemuck::{Pod, Zeroable};
+use ultraviolet::{Rotor3, Vec3};
+
+
+const BALL: u32 = 1;
+const CUBOID: u32 = 2;
+const CYLINDER: u32 = 3;
+
+const UNION: u32 = 0;
+const INTERSECTION: u32 = 1;
+const DIFFERENCE: u32 = 2;
+
+/// Convex shape taking part in a boolean operation. It's only rendered as a part of its `Csg`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CsgOperand {
+    pub center: Vec3,
+    kind: u32,
+    size: Vec3, // ball - radius in x, cuboid - half size, cylinder - radius and height
+    _pad0: u32,
+    axis_x: Vec3,
+    _pad1: u32,
+    axis_y: Vec3, // cylinder's axis
+    _pad2: u32,
+}
+
+/// Boolean combination of two operands, evaluated by intersecting the intervals the ray spends inside them.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Csg {
+    pub a: CsgOperand,
+    pub b: CsgOperand,
+    op: u32,
+    pub material: u32,
+    _padding: [u32; 2],
+}
+
+unsafe impl Pod for CsgOperand {}
+unsafe impl Zeroable for CsgOperand {}
+unsafe impl Pod for Csg {}
+unsafe impl Zeroable for Csg {}
+
+impl CsgOperand {
+    pub fn ball(center: Vec3, radius: f32) -> Self {
+        Self::new(BALL, center, Vec3::new(radius, 0., 0.), Rotor3::identity())
+    }
+
+    pub fn cuboid(center: Vec3, half_size: Vec3, rotation: Rotor3) -> Self {
+        Self::new(CUBOID, center, half_size, rotation)
+    }
+
+    /// Capped cylinder centered at `center`, with `axis` running through both caps.
+    pub fn cylinder(center: Vec3, axis: Vec3, radius: f32, height: f32) -> Self {
+        let axis = axis.normalized();
+        // The rotation between opposite vectors isn't unique, `from_rotation_between` returns NaN for it
+        let rotation = if axis.dot(Vec3::unit_y()) < -0.9999 {
+            Rotor3::from_rotation_yz(PI)
+        } else {
+            Rotor3::from_rotation_between(Vec3::unit_y(), axis)
+        };
+        Self::new(CYLINDER, center, Vec3::new(radius, height, 0.), rotation)
+    }
+
+    fn new(kind: u32, center: Vec3, size: Vec3, rotation: Rotor3) -> Self {
+        Self {
+            center,
+            kind,
+            size,
+            axis_x: rotation * Vec3::unit_x(),
+            axis_y: rotation * Vec3::unit_y(),
+            _pad0: 0,
+            _pad1: 0,
+            _pad2: 0,
+        }
+    }
+}
+
+impl Csg {
+    pub fn union(a: CsgOperand, b: CsgOperand, material: u32) -> Self {
+        Self::new(UNION, a, b, material)
+    }
+
+    pub fn intersection(a: CsgOperand, b: CsgOperand, material: u32) -> Self {
+        Self::new(INTERSECTION, a, b, material)
+    }
+
+    /// `a` with `b` carved out of it.
+    pub fn difference(a: CsgOperand, b: CsgOperand, material: u32) -> Self {
+        Self::new(DIFFERENCE, a, b, material)
+    }
+
+    fn new(op: u32, a: CsgOperand, b: CsgOperand, material: u32) -> Self {
+        Self { a, b, op, material, _padding: Default::default() }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cylinder_axis() {
+        for axis in [Vec3::unit_y(), -Vec3::unit_y(), Vec3::new(0., -2., 0.), Vec3::unit_x()] {
+            let cylinder = CsgOperand::cylinder(Vec3::zero(), axis, 1., 2.);
+            assert!((cylinder.axis_y - axis.normalized()).mag() < 1e-5, "{:?}", cylinder.axis_y);
+            assert!(cylinder.axis_x.dot(cylinder.axis_y).abs() < 1e-5 && (cylinder.axis_x.mag() - 1.).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::{borrow::Cow, sync::Arc};
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::film::{Aovs, Film};
+
+
+/// Mirrors `Params` in denoise.wgsl.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Params {
+    width: u32,
+    height: u32,
+    step: i32,
+    sigma_color: f32,
+    sigma_normal: f32,
+    sigma_albedo: f32,
+    _padding: [u32; 2],
+}
+
+unsafe impl Pod for Params {}
+unsafe impl Zeroable for Params {}
+
+/// Edge-avoiding à-trous wavelet denoiser, run on the GPU over the collected film.
+/// The first-hit normals and albedos keep it from blurring across edges and textures.
+pub struct Denoiser {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: wgpu::ComputePipeline,
+    /// Levels of the wavelet, each doubles the filter's footprint.
+    pub levels: u32,
+    /// How different the colors of neighbours may be, halved with every level.
+    pub sigma_color: f32,
+    pub sigma_normal: f32,
+    pub sigma_albedo: f32,
+}
+
+impl Denoiser {
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>, levels: u32) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Denoise shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("denoise.wgsl"))),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Denoise pipeline"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Denoiser { device, queue, pipeline, levels, sigma_color: 1., sigma_normal: 0.3, sigma_albedo: 0.1 }
+    }
+
+    pub async fn denoise(&self, film: &Film, aovs: &Aovs) -> Film {
+        let (width, height) = film.image.dimensions();
+        let pixels = (width * height) as usize;
+        let size = (std::mem::size_of::<[f32; 4]>() * pixels) as wgpu::BufferAddress;
+
+        let color: Vec<[f32; 4]> = film.image.pixels().map(|pixel| [pixel[0], pixel[1], pixel[2], 1.]).collect();
+        let normals: Vec<[f32; 4]> = (0..pixels).map(|i| with_w(aovs.normal_at(i))).collect();
+        let albedos: Vec<[f32; 4]> = (0..pixels).map(|i| with_w(aovs.albedo_at(i))).collect();
+
+        let storage = |label: &str, contents: &[[f32; 4]]| {
+            self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(contents),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            })
+        };
+        // Levels ping-pong between the two color buffers
+        let buffers = [storage("Denoise color buffer", &color), storage("Denoise scratch buffer", &color)];
+        let normals_buffer = storage("Denoise normals buffer", &normals);
+        let albedos_buffer = storage("Denoise albedos buffer", &albedos);
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        for level in 0..self.levels {
+            let params = Params {
+                width,
+                height,
+                step: 1 << level,
+                sigma_color: self.sigma_color / (1 << level) as f32,
+                sigma_normal: self.sigma_normal,
+                sigma_albedo: self.sigma_albedo,
+                _padding: Default::default(),
+            };
+            let params_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Denoise params buffer"),
+                contents: bytemuck::cast_slice(&[params]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
+            let (input, output) = (&buffers[level as usize % 2], &buffers[(level as usize + 1) % 2]);
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Denoise bind group"),
+                layout: &self.pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: input.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: output.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: normals_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: albedos_buffer.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 4, resource: params_buffer.as_entire_binding() },
+                ],
+            });
+
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &bind_group, &[]);
+            cpass.dispatch_workgroups(width.div_ceil(8), height.div_ceil(8), 1);
+        }
+
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Denoise staging buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_buffer_to_buffer(&buffers[self.levels as usize % 2], 0, &staging_buffer, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let the_slice = staging_buffer.slice(..);
+        the_slice.map_async(wgpu::MapMode::Read, move |v| {
+            sender.send(v).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.await.unwrap().unwrap();
+
+        let data = the_slice.get_mapped_range();
+        let denoised = bytemuck::cast_slice::<u8, [f32; 4]>(&data);
+        let mut result = Film::new(width, height);
+        for (i, color) in denoised.iter().enumerate() {
+            result.put(i as u32 % width, i as u32 / width, [color[0], color[1], color[2]]);
+        }
+        result
+    }
+}
+
+fn with_w(v: [f32; 3]) -> [f32; 4] {
+    [v[0], v[1], v[2], 0.]
+}
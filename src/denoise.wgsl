@@ -0,0 +1,68 @@
+// Edge-avoiding à-trous wavelet filter (Dammertz et al.), one dispatch per level of the wavelet.
+// Neighbours only contribute where their color, normal and albedo resemble the center pixel's.
+
+struct Params {
+    width: u32,
+    height: u32,
+    step: i32, // distance between the taps, doubled with every level
+    sigma_color: f32,
+    sigma_normal: f32,
+    sigma_albedo: f32,
+}
+
+@group(0) @binding(0)
+var<storage, read> input: array<vec4<f32>>;
+
+@group(0) @binding(1)
+var<storage, read_write> output: array<vec4<f32>>;
+
+@group(0) @binding(2)
+var<storage, read> normals: array<vec4<f32>>;
+
+@group(0) @binding(3)
+var<storage, read> albedos: array<vec4<f32>>;
+
+@group(0) @binding(4)
+var<uniform> params: Params;
+
+// B3 spline, separable
+const KERNEL: array<f32, 5> = array<f32, 5>(1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.);
+
+fn edge_weight(difference: vec3<f32>, sigma: f32) -> f32 {
+    return exp(-dot(difference, difference) / max(sigma * sigma, 1e-8));
+}
+
+@compute
+@workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if global_id.x >= params.width || global_id.y >= params.height {
+        return;
+    }
+    let center = vec2<i32>(global_id.xy);
+    let index = global_id.y * params.width + global_id.x;
+    let color = input[index].rgb;
+    let normal = normals[index].xyz;
+    let albedo = albedos[index].rgb;
+
+    var sum = vec3<f32>(0.);
+    var weight_sum = 0.;
+    for (var dy: i32 = -2; dy <= 2; dy++) {
+        for (var dx: i32 = -2; dx <= 2; dx++) {
+            let tap = center + vec2<i32>(dx, dy) * params.step;
+            if tap.x < 0 || tap.y < 0 || tap.x >= i32(params.width) || tap.y >= i32(params.height) {
+                continue;
+            }
+            let tap_index = u32(tap.y) * params.width + u32(tap.x);
+            let tap_color = input[tap_index].rgb;
+
+            let weight = KERNEL[dx + 2] * KERNEL[dy + 2]
+                * edge_weight(tap_color - color, params.sigma_color)
+                * edge_weight(normals[tap_index].xyz - normal, params.sigma_normal)
+                * edge_weight(albedos[tap_index].rgb - albedo, params.sigma_albedo);
+            sum += tap_color * weight;
+            weight_sum += weight;
+        }
+    }
+
+    output[index] = vec4<f32>(sum / weight_sum, 1.);
+}
@@ -0,0 +1,264 @@
+use std::{fs::File, io::BufWriter, path::Path, time::Duration};
+
+use exr::prelude::{f16, AnyChannel, AnyChannels, Encoding, FlatSamples, Image, ImageAttributes, IntegerBounds,
+    Layer, LayerAttributes, SmallVec, WritableImage};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, Rgb, Rgb32FImage, RgbImage};
+
+use crate::ray::Ray;
+use crate::tonemap::OutputTransform;
+
+
+/// How a film is written, the format itself is picked from the file's extension.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SaveOptions {
+    /// Write OpenEXR channels as 16-bit half floats instead of 32-bit floats.
+    pub half_float: bool,
+    /// Applied to formats with 8 bits per channel only, HDR formats keep the linear values.
+    pub output_transform: OutputTransform,
+    /// Also write the auxiliary outputs, see `Aovs`.
+    pub aovs: bool,
+}
+
+/// Linear, unclamped framebuffer the rendered pixels are gathered into.
+pub struct Film {
+    pub image: Rgb32FImage,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        Film { image: Rgb32FImage::new(width, height) }
+    }
+
+    pub fn put(&mut self, x: u32, y: u32, color: [f32; 3]) {
+        self.image.put_pixel(x, y, Rgb(color));
+    }
+
+    /// `.exr` and `.hdr` keep the full range of the film, any other format supported by `image` gets 8 bits.
+    pub fn save(&self, path: &str, options: &SaveOptions) -> Result<(), String> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("exr") => self.save_exr(path, options.half_float),
+            Some("hdr") => self.save_hdr(path),
+            _ => self.to_ldr(&options.output_transform).save(path).map_err(|e| format!("{}: {}", path, e)),
+        }
+    }
+
+    fn save_exr(&self, path: &str, half_float: bool) -> Result<(), String> {
+        let (width, height) = (self.image.width() as usize, self.image.height() as usize);
+        let color = |x: usize, y: usize| self.image.get_pixel(x as u32, y as u32).0;
+
+        let result = if half_float {
+            exr::prelude::write_rgb_file(path, width, height, |x, y| {
+                let [r, g, b] = color(x, y);
+                (f16::from_f32(r), f16::from_f32(g), f16::from_f32(b))
+            })
+        } else {
+            exr::prelude::write_rgb_file(path, width, height, |x, y| {
+                let [r, g, b] = color(x, y);
+                (r, g, b)
+            })
+        };
+        result.map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn save_hdr(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let pixels: Vec<Rgb<f32>> = self.image.pixels().copied().collect();
+        image::codecs::hdr::HdrEncoder::new(BufWriter::new(file))
+            .encode(&pixels, self.image.width() as usize, self.image.height() as usize)
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn to_ldr(&self, transform: &OutputTransform) -> RgbImage {
+        RgbImage::from_fn(self.image.width(), self.image.height(), |x, y| {
+            Rgb(transform.apply(self.image.get_pixel(x, y).0))
+        })
+    }
+}
+
+/// Animated GIF the frames of an animation are appended to as they're rendered.
+pub struct GifWriter {
+    encoder: GifEncoder<BufWriter<File>>,
+    delay: Delay,
+    transform: OutputTransform,
+}
+
+impl GifWriter {
+    pub fn create(path: &str, fps: u32, transform: OutputTransform) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), 10);
+        encoder.set_repeat(Repeat::Infinite).map_err(|e| format!("{}: {}", path, e))?;
+        let delay = Delay::from_saturating_duration(Duration::from_secs_f32(1. / fps.max(1) as f32));
+        Ok(GifWriter { encoder, delay, transform })
+    }
+
+    pub fn push(&mut self, film: &Film) -> Result<(), String> {
+        let image = DynamicImage::ImageRgb8(film.to_ldr(&self.transform)).to_rgba8();
+        self.encoder.encode_frame(Frame::from_parts(image, 0, 0, self.delay)).map_err(|e| e.to_string())
+    }
+}
+
+/// Auxiliary outputs of the first hit of every pixel, written as the layers of a single OpenEXR file:
+/// `depth.Z`, `normal.XYZ`, `albedo.RGB` and the integer `id.material`, `id.object` and `id.primitive`.
+/// `id.object` numbers the kinds as the structs in shader.wgsl do: 1 ball, 2 triangle, 3 plane, 4 quad, 5 disk,
+/// 6 cuboid, 7 cylinder, 8 sdf and 9 csg.
+/// Pixels seeing the sky get 0 depth, normal and albedo, object 0 and material `u32::MAX`.
+pub struct Aovs {
+    width: u32,
+    height: u32,
+    depth: Vec<f32>,
+    normal: [Vec<f32>; 3],
+    albedo: [Vec<f32>; 3],
+    material: Vec<u32>,
+    object: Vec<u32>,
+    primitive: Vec<u32>,
+}
+
+impl Aovs {
+    pub fn new(width: u32, height: u32) -> Self {
+        let size = (width * height) as usize;
+        let channel = || vec![0f32; size];
+        Aovs {
+            width,
+            height,
+            depth: channel(),
+            normal: [channel(), channel(), channel()],
+            albedo: [channel(), channel(), channel()],
+            material: vec![0; size],
+            object: vec![0; size],
+            primitive: vec![0; size],
+        }
+    }
+
+    pub fn normal_at(&self, index: usize) -> [f32; 3] {
+        self.normal.each_ref().map(|axis| axis[index])
+    }
+
+    pub fn albedo_at(&self, index: usize) -> [f32; 3] {
+        self.albedo.each_ref().map(|channel| channel[index])
+    }
+
+    pub fn put(&mut self, ray: &Ray) {
+        let index = (ray.screen_y * self.width + ray.screen_x) as usize;
+        self.depth[index] = ray.depth;
+        for axis in 0..3 {
+            self.normal[axis][index] = ray.normal[axis];
+            self.albedo[axis][index] = ray.albedo[axis];
+        }
+        self.material[index] = ray.material;
+        self.object[index] = ray.object_id;
+        self.primitive[index] = ray.primitive_id;
+    }
+
+    /// Where the auxiliary outputs of the image at `path` go: `render.png` gets `render.aovs.exr`.
+    pub fn path_for(path: &str) -> String {
+        Path::new(path).with_extension("aovs.exr").to_string_lossy().into_owned()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let size = (self.width as usize, self.height as usize);
+        let layer = |name: &str, channels: Vec<AnyChannel<FlatSamples>>| {
+            Layer::new(size, LayerAttributes::named(name), Encoding::FAST_LOSSLESS, AnyChannels::sort(SmallVec::from_vec(channels)))
+        };
+        let float = |name: &str, values: &Vec<f32>| AnyChannel::new(name, FlatSamples::F32(values.clone()));
+        let integer = |name: &str, values: &Vec<u32>| AnyChannel::new(name, FlatSamples::U32(values.clone()));
+
+        let layers = vec![
+            layer("depth", vec![float("Z", &self.depth)]),
+            layer("normal", vec![float("X", &self.normal[0]), float("Y", &self.normal[1]), float("Z", &self.normal[2])]),
+            layer("albedo", vec![float("R", &self.albedo[0]), float("G", &self.albedo[1]), float("B", &self.albedo[2])]),
+            layer("id", vec![
+                integer("material", &self.material),
+                integer("object", &self.object),
+                integer("primitive", &self.primitive),
+            ]),
+        ];
+
+        Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), layers)
+            .write()
+            .to_file(path)
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// File in the temporary directory, unique to a test of this process and removed when dropped, even by a failing test.
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(test: &str, name: &str) -> Self {
+            TempFile(std::env::temp_dir().join(format!("rays_gpu.{}.{}.{}", std::process::id(), test, name)))
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_hdr_formats_keep_range() {
+        let mut film = Film::new(2, 1);
+        film.put(0, 0, [4., 0.5, 0.]);
+        film.put(1, 0, [0., 1., 16.]);
+
+        for (name, half_float) in [("film.exr", false), ("film_half.exr", true), ("film.hdr", false)] {
+            let file = TempFile::new("test_hdr_formats_keep_range", name);
+            let path = file.path();
+            film.save(path, &SaveOptions { half_float, ..Default::default() }).unwrap();
+
+            let saved: Vec<[f32; 3]> = if name.ends_with(".exr") {
+                image::open(path).unwrap().to_rgb32f().pixels().map(|pixel| pixel.0).collect()
+            } else {
+                // `image::open` converts Radiance HDR to 8 bits
+                let reader = std::io::BufReader::new(File::open(path).unwrap());
+                let decoder = image::codecs::hdr::HdrDecoder::new(reader).unwrap();
+                decoder.read_image_hdr().unwrap().into_iter().map(|pixel| pixel.0).collect()
+            };
+            assert_eq!(saved, vec![[4., 0.5, 0.], [0., 1., 16.]]);
+        }
+    }
+
+    #[test]
+    fn test_aovs_are_written_as_layers() {
+        let mut aovs = Aovs::new(2, 1);
+        let mut ray = Ray::new(Default::default(), Default::default(), None, 1, 0);
+        ray.depth = 2.5;
+        ray.normal = ultraviolet::Vec3::unit_y();
+        ray.material = 7;
+        aovs.put(&ray);
+
+        assert_eq!(Aovs::path_for("renders/output.png"), "renders/output.aovs.exr");
+        let file = TempFile::new("test_aovs_are_written_as_layers", "film.aovs.exr");
+        let path = file.path();
+        aovs.save(path).unwrap();
+
+        use exr::prelude::{ReadChannels, ReadLayers};
+        let image = exr::prelude::read().no_deep_data().largest_resolution_level().all_channels()
+            .all_layers().all_attributes().from_file(path).unwrap();
+        let channel = |layer: &str, channel: &str| {
+            let layer = image.layer_data.iter()
+                .find(|candidate| candidate.attributes.layer_name.as_ref().is_some_and(|name| name == layer))
+                .unwrap();
+            let channel = layer.channel_data.list.iter().find(|candidate| candidate.name == *channel).unwrap();
+            channel.sample_data.values_as_f32().collect::<Vec<f32>>()
+        };
+        assert_eq!(channel("depth", "Z"), vec![0., 2.5]);
+        assert_eq!(channel("normal", "Y"), vec![0., 1.]);
+        assert_eq!(channel("id", "material"), vec![0., 7.]);
+    }
+}
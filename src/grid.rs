@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+
+/// Dense 3D density grid, stored normalised to [0, 1] so the owning volume's density is its majorant.
+pub struct DensityGrid {
+    pub size: [u32; 3],
+    pub values: Vec<f32>,
+}
+
+impl DensityGrid {
+    pub fn new(size: [u32; 3], mut values: Vec<f32>) -> Result<Self, String> {
+        let expected = size.iter().map(|&n| n as usize).product::<usize>();
+        if values.len() != expected || expected == 0 {
+            return Err(format!("Grid of size {:?} needs {} values, got {}", size, expected, values.len()));
+        }
+
+        let max = values.iter().cloned().fold(0f32, f32::max);
+        if max > 0. {
+            values.iter_mut().for_each(|value| *value = value.max(0.) / max);
+        }
+        Ok(DensityGrid { size, values })
+    }
+
+    /// A raw grid when its `size` is given, a dense one otherwise.
+    pub fn load(path: &str, size: Option<[u32; 3]>) -> Result<Self, String> {
+        match size {
+            Some(size) => Self::load_raw(path, size),
+            None => Self::load_dense(path),
+        }
+    }
+
+    /// Headerless little-endian `f32` values, x varying fastest.
+    pub fn load_raw(path: &str, size: [u32; 3]) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::new(size, read_floats(&bytes))
+    }
+
+    /// The raw layout preceded by a header: the `DGRD` magic and three little-endian `u32` dimensions.
+    pub fn load_dense(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        if bytes.len() < 16 || &bytes[..4] != b"DGRD" {
+            return Err(format!("{}: not a dense grid file", path));
+        }
+
+        let dimension = |i: usize| u32::from_le_bytes(bytes[4 + 4 * i..8 + 4 * i].try_into().unwrap());
+        Self::new([dimension(0), dimension(1), dimension(2)], read_floats(&bytes[16..]))
+    }
+}
+
+fn read_floats(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+/// Uploads the grid as an `R32Float` 3D texture. Without a grid a single voxel of full density stands in,
+/// which renders grid-driven volumes as homogeneous ones.
+pub fn prepare_grid_texture(
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    layout: &wgpu::BindGroupLayout,
+    grid: Option<&DensityGrid>,
+) -> wgpu::BindGroup {
+    let (size, values) = match grid {
+        Some(grid) => (grid.size, &grid.values[..]),
+        None => ([1, 1, 1], &[1f32][..]),
+    };
+
+    let size = wgpu::Extent3d {
+        width: size[0],
+        height: size[1],
+        depth_or_array_layers: size[2],
+    };
+
+    let grid_texture = device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::R32Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        label: Some("density_grid"),
+        view_formats: &[],
+    });
+
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &grid_texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        bytemuck::cast_slice(values),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(size.width * 4),
+            rows_per_image: Some(size.height),
+        },
+        size,
+    );
+
+    let grid_view = grid_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&grid_view),
+            },
+        ],
+        label: Some("density grid bind group"),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid_is_normalised() {
+        let grid = DensityGrid::new([2, 1, 1], vec![0.5, 2.]).unwrap();
+        assert_eq!(grid.values, vec![0.25, 1.]);
+
+        assert!(DensityGrid::new([2, 2, 1], vec![0.5, 2.]).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+use std::ops::{Add, Mul, Sub};
+
+use serde::{Deserialize, Serialize};
+use ultraviolet::{Rotor3, Vec3};
+
+use crate::camera_path::CameraPath;
+use crate::scene::Scene;
+
+
+/// Values a track can interpolate, `f32` and `Vec3` among them.
+pub trait Animatable: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self> {}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>> Animatable for T {}
+
+/// How a track gets from a key to the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Holds the value until the next key.
+    Step,
+    /// Passes smoothly through the neighbouring keys as well.
+    CatmullRom,
+    /// CSS `ease-in-out`, the cubic Bezier with control points (0.42, 0) and (0.58, 1).
+    EaseInOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe<T> {
+    /// Fractional frames are allowed.
+    pub frame: f32,
+    pub value: T,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+/// Keys of a single property, sorted by their frames.
+/// Before the first key and after the last one the value stays constant.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(from = "Vec<Keyframe<T>>", into = "Vec<Keyframe<T>>")]
+#[serde(bound(serialize = "T: Clone + Serialize"))]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T> From<Vec<Keyframe<T>>> for Track<T> {
+    fn from(mut keys: Vec<Keyframe<T>>) -> Self {
+        keys.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+        Track { keys }
+    }
+}
+
+impl<T> From<Track<T>> for Vec<Keyframe<T>> {
+    fn from(track: Track<T>) -> Self {
+        track.keys
+    }
+}
+
+impl<T: Animatable> Track<T> {
+    /// `None` when the track has no keys.
+    pub fn sample(&self, frame: f32) -> Option<T> {
+        let (first, last) = (self.keys.first()?, self.keys.last()?);
+        if frame <= first.frame {
+            return Some(first.value);
+        }
+        if frame >= last.frame {
+            return Some(last.value);
+        }
+
+        let next = self.keys.partition_point(|key| key.frame <= frame);
+        let (from, to) = (&self.keys[next - 1], &self.keys[next]);
+        let t = (frame - from.frame) / (to.frame - from.frame);
+
+        Some(match from.interpolation {
+            Interpolation::Step => from.value,
+            Interpolation::Linear => lerp(from.value, to.value, t),
+            Interpolation::EaseInOut => lerp(from.value, to.value, ease_in_out(t)),
+            Interpolation::CatmullRom => {
+                // The ends of the track repeat their key in place of the missing neighbour
+                let before = &self.keys[next.saturating_sub(2)];
+                let after = &self.keys[(next + 1).min(self.keys.len() - 1)];
+                catmull_rom([before, from, to, after], t)
+            },
+        })
+    }
+}
+
+fn lerp<T: Animatable>(from: T, to: T, t: f32) -> T {
+    from + (to - from) * t
+}
+
+fn ease_in_out(x: f32) -> f32 {
+    let bezier = |a: f32, b: f32, s: f32| 3. * (1. - s) * (1. - s) * s * a + 3. * (1. - s) * s * s * b + s * s * s;
+    // x(s) is monotonic, so bisection finds the curve's parameter for x
+    let (mut low, mut high) = (0f32, 1f32);
+    for _ in 0..24 {
+        let middle = (low + high) / 2.;
+        if bezier(0.42, 0.58, middle) < x {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    bezier(0., 1., (low + high) / 2.)
+}
+
+/// Cubic Hermite segment between the middle two keys with Catmull-Rom tangents,
+/// which account for keys being unevenly spaced in time.
+fn catmull_rom<T: Animatable>([k0, k1, k2, k3]: [&Keyframe<T>; 4], t: f32) -> T {
+    let span = k2.frame - k1.frame;
+    let tangent1 = (k2.value - k0.value) * (span / (k2.frame - k0.frame));
+    let tangent2 = (k3.value - k1.value) * (span / (k3.frame - k1.frame));
+
+    let (t2, t3) = (t * t, t * t * t);
+    k1.value * (2. * t3 - 3. * t2 + 1.)
+        + tangent1 * (t3 - 2. * t2 + t)
+        + k2.value * (3. * t2 - 2. * t3)
+        + tangent2 * (t3 - t2)
+}
+
+/// Objects a track can move, see `Scene::motion_mut`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ObjectKind {
+    Ball,
+    Plane,
+    Quad,
+    Disk,
+    Cuboid,
+    Cylinder,
+    Sdf,
+    Volume,
+}
+
+impl ObjectKind {
+    pub const ALL: [ObjectKind; 8] = [
+        ObjectKind::Ball, ObjectKind::Plane, ObjectKind::Quad, ObjectKind::Disk,
+        ObjectKind::Cuboid, ObjectKind::Cylinder, ObjectKind::Sdf, ObjectKind::Volume,
+    ];
+}
+
+/// Moves the `index`-th object of its kind by `translation`, relative to where the scene put it.
+/// The velocity of the track around the frame adds to the object's own, for motion blur.
+/// `rotation` turns it by angles in degrees about the x, y and z axes, applied in that order, and `scale` grows it,
+/// both about the point placing it (see `Scene::transform`). Only translation blurs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectTrack {
+    pub object: ObjectKind,
+    pub index: usize,
+    #[serde(default)]
+    pub translation: Track<Vec3>,
+    #[serde(default)]
+    pub rotation: Track<Vec3>,
+    #[serde(default)]
+    pub scale: Track<f32>,
+}
+
+fn euler_rotation(degrees: Vec3) -> Rotor3 {
+    Rotor3::from_rotation_xy(degrees.z.to_radians())
+        * Rotor3::from_rotation_xz(degrees.y.to_radians())
+        * Rotor3::from_rotation_yz(degrees.x.to_radians())
+}
+
+/// Parameters of the `index`-th material of the scene.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaterialTrack {
+    pub index: usize,
+    pub albedo: Track<Vec3>,
+    pub secondary: Track<Vec3>,
+    pub scale: Track<f32>,
+    pub turbulence: Track<f32>,
+}
+
+/// Keyframed properties of an animation, the `keyframes` table of its file (see `Animation`) such as
+///
+/// ```toml
+/// [keyframes]
+/// eye = [
+///     { frame = 0, value = { x = 4, y = 1, z = 5 }, interpolation = "catmull-rom" },
+///     { frame = 120, value = { x = 1, y = 1, z = 20 } },
+/// ]
+/// fov = [{ frame = 0, value = 30, interpolation = "ease-in-out" }, { frame = 60, value = 45 }]
+///
+/// [keyframes.camera_path]
+/// shape = "spline"
+/// points = [{ x = 4, y = 1, z = 5 }, { x = 0, y = 3, z = 9 }, { x = -4, y = 1, z = 5 }]
+/// target = { x = 0, y = 0, z = 0 }
+/// start = 0
+/// end = 120
+///
+/// [[keyframes.objects]]
+/// object = "ball"
+/// index = 0
+/// translation = [{ frame = 0, value = { x = 0, y = 0, z = 0 } }, { frame = 30, value = { x = 0, y = 2, z = 0 } }]
+///
+/// [[keyframes.objects]]
+/// object = "cuboid"
+/// index = 1
+/// rotation = [{ frame = 0, value = { x = 0, y = 0, z = 0 } }, { frame = 120, value = { x = 0, y = 360, z = 0 } }]
+/// scale = [{ frame = 0, value = 1 }, { frame = 60, value = 1.5 }]
+/// ```
+///
+/// The scene itself is the built-in one (see `Scene::default`), tracks pick its objects and materials by index.
+/// Properties without keys keep the scene's own values, keys of the eye or target win over the camera path.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keyframes {
+    pub camera_path: Option<CameraPath>,
+    pub eye: Track<Vec3>,
+    pub target: Track<Vec3>,
+    pub fov: Track<f32>,
+    pub objects: Vec<ObjectTrack>,
+    pub materials: Vec<MaterialTrack>,
+}
+
+impl Keyframes {
+    pub fn apply(&self, scene: &mut Scene, frame: f32) -> Result<(), String> {
+        if let Some(path) = &self.camera_path {
+            let (eye, target) = path.camera_at(frame);
+            scene.eye = eye;
+            if let Some(target) = target {
+                scene.target = target;
+            }
+        }
+        if let Some(eye) = self.eye.sample(frame) {
+            scene.eye = eye;
+        }
+        if let Some(target) = self.target.sample(frame) {
+            scene.target = target;
+        }
+        if let Some(fov) = self.fov.sample(frame) {
+            scene.fov = fov;
+        }
+
+        for track in &self.objects {
+            let (position, velocity) = scene.motion_mut(track.object, track.index)
+                .ok_or_else(|| format!("The scene has no {:?} number {}", track.object, track.index))?;
+            if let Some(translation) = track.translation.sample(frame) {
+                *position += translation;
+            }
+            if let (Some(before), Some(after)) = (track.translation.sample(frame - 0.5), track.translation.sample(frame + 0.5)) {
+                *velocity += after - before;
+            }
+
+            let rotation = track.rotation.sample(frame).map(euler_rotation);
+            let scale = track.scale.sample(frame);
+            if rotation.is_some() || scale.is_some() {
+                scene.transform(track.object, track.index, rotation.unwrap_or_else(Rotor3::identity), scale.unwrap_or(1.));
+            }
+        }
+
+        for track in &self.materials {
+            let material = scene.materials.get_mut(track.index)
+                .ok_or_else(|| format!("The scene has no material number {}", track.index))?;
+            if let Some(albedo) = track.albedo.sample(frame) {
+                material.albedo = albedo;
+            }
+            if let Some(secondary) = track.secondary.sample(frame) {
+                material.secondary = secondary;
+            }
+            if let Some(scale) = track.scale.sample(frame) {
+                material.scale = scale;
+            }
+            if let Some(turbulence) = track.turbulence.sample(frame) {
+                material.turbulence = turbulence;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(keys: &[(f32, f32)], interpolation: Interpolation) -> Track<f32> {
+        keys.iter().map(|&(frame, value)| Keyframe { frame, value, interpolation }).collect::<Vec<_>>().into()
+    }
+
+    #[test]
+    fn test_track_interpolation() {
+        assert_eq!(Track::<f32>::default().sample(3.), None);
+
+        let linear = track(&[(10., 2.), (0., 0.)], Interpolation::Linear);
+        assert_eq!(linear.sample(-5.), Some(0.));
+        assert_eq!(linear.sample(5.), Some(1.));
+        assert_eq!(linear.sample(15.), Some(2.));
+
+        let step = track(&[(0., 0.), (10., 2.)], Interpolation::Step);
+        assert_eq!(step.sample(9.9), Some(0.));
+        assert_eq!(step.sample(10.), Some(2.));
+
+        let eased = track(&[(0., 0.), (10., 1.)], Interpolation::EaseInOut);
+        assert!((eased.sample(5.).unwrap() - 0.5).abs() < 1e-4);
+        assert!(eased.sample(1.).unwrap() < 0.1);
+        assert!(eased.sample(9.).unwrap() > 0.9);
+
+        // Keys on a line stay on it, whatever their spacing
+        let spline = track(&[(0., 0.), (1., 1.), (4., 4.), (5., 5.)], Interpolation::CatmullRom);
+        for frame in [0.5, 2., 3.5, 4.5] {
+            assert!((spline.sample(frame).unwrap() - frame).abs() < 1e-4);
+        }
+        let spline = track(&[(0., 0.), (1., 1.), (2., 0.)], Interpolation::CatmullRom);
+        assert_eq!(spline.sample(1.), Some(1.));
+        assert!(spline.sample(0.9).unwrap() > 0.9);
+    }
+
+    #[test]
+    fn test_keyframes_from_toml() {
+        let keyframes: Keyframes = toml::from_str(r#"
+            fov = [{ frame = 0, value = 30 }, { frame = 10, value = 50 }]
+
+            [[objects]]
+            object = "plane"
+            index = 0
+            translation = [{ frame = 0, value = { x = 0, y = 0, z = 0 }, interpolation = "step" }, { frame = 5, value = { x = 0, y = 1, z = 0 } }]
+
+            [[materials]]
+            index = 1
+            albedo = [{ frame = 0, value = { x = 1, y = 0, z = 0 } }]
+        "#).unwrap();
+
+        let mut scene = Scene::default();
+        keyframes.apply(&mut scene, 5.).unwrap();
+        assert_eq!(scene.fov, 40.);
+        assert_eq!(scene.planes[0].point, Vec3::new(0., 0.5, 0.));
+        assert_eq!(scene.materials[1].albedo, Vec3::unit_x());
+
+        let missing = Keyframes { objects: vec![ObjectTrack { index: 3, ..keyframes.objects[0].clone() }], ..Default::default() };
+        assert!(missing.apply(&mut Scene::default(), 0.).is_err());
+        // Moving objects of every kind are blurred by their velocity
+        for object in [ObjectKind::Ball, ObjectKind::Cuboid, ObjectKind::Sdf, ObjectKind::Volume] {
+            let mut scene = Scene::default();
+            let start = *scene.motion_mut(object, 0).unwrap().0;
+            let rising = ObjectTrack {
+                object,
+                index: 0,
+                translation: vec![
+                    Keyframe { frame: 0., value: Vec3::zero(), interpolation: Interpolation::Linear },
+                    Keyframe { frame: 10., value: Vec3::new(0., 5., 0.), interpolation: Interpolation::Linear },
+                ].into(),
+                rotation: Track::default(),
+                scale: Track::default(),
+            };
+            Keyframes { objects: vec![rising], ..Default::default() }.apply(&mut scene, 4.).unwrap();
+            let (position, velocity) = scene.motion_mut(object, 0).unwrap();
+            assert_eq!(*position - start, Vec3::new(0., 2., 0.));
+            assert_eq!(*velocity, Vec3::new(0., 0.5, 0.));
+        }
+
+        // Turning and growing about the point placing the object
+        let spinning: ObjectTrack = toml::from_str(r#"
+            object = "cuboid"
+            index = 0
+            rotation = [{ frame = 0, value = { x = 0, y = 0, z = 0 } }, { frame = 10, value = { x = 0, y = 0, z = 180 } }]
+            scale = [{ frame = 0, value = 2 }]
+        "#).unwrap();
+        let mut scene = Scene::default();
+        let cuboid = scene.cuboids[0];
+        Keyframes { objects: vec![spinning], ..Default::default() }.apply(&mut scene, 5.).unwrap();
+        assert_eq!(scene.cuboids[0].center, cuboid.center);
+        assert_eq!(scene.cuboids[0].half_size, cuboid.half_size * 2.);
+        assert!(scene.cuboids[0].axis_x.dot(cuboid.axis_x).abs() < 1e-5);
+        assert!((scene.cuboids[0].axis_x.mag() - 1.).abs() < 1e-5);
+
+        assert!(toml::from_str::<Keyframes>("fov = [{ frame = 0, value = 30, interpolation = \"cubic\" }]").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+
+/// Uploads normal and bump maps as layers of a single 2D array texture.
+/// Layers must share one size, so every map is resized to the largest width and height found.
+pub fn prepare_maps_texture(
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    layout: &wgpu::BindGroupLayout,
+    maps: &[image::RgbaImage],
+) -> wgpu::BindGroup {
+    let width = maps.iter().map(|map| map.width()).max().unwrap_or(1);
+    let height = maps.iter().map(|map| map.height()).max().unwrap_or(1);
+    // An array texture can't be empty, a flat normal stands in when the scene has no maps
+    let placeholder = [image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255]))];
+    let maps = if maps.is_empty() { &placeholder[..] } else { maps };
+
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: maps.len() as u32,
+    };
+
+    let maps_texture = device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        label: Some("maps_texture"),
+        view_formats: &[],
+    });
+
+    for (layer, map) in maps.iter().enumerate() {
+        let resized;
+        let map = if map.dimensions() == (width, height) {
+            map
+        } else {
+            resized = image::imageops::resize(map, width, height, image::imageops::FilterType::Triangle);
+            &resized
+        };
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &maps_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            map,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4),
+                rows_per_image: Some(height),
+            },
+            wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        );
+    }
+
+    let texture_view = maps_texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("maps sampler"),
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::Repeat,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+        ],
+        label: Some("maps bind group"),
+    })
+}
@@ -0,0 +1,88 @@
+use bytemuck::{Pod, Zeroable};
+use ultraviolet::Vec3;
+
+// Scattering models understood by `ray_color` in the shader.
+pub const DIFFUSE: u32 = 0;
+pub const METAL: u32 = 1;
+
+/// Procedural albedo sources, evaluated in the shader at the hit point (world space).
+/// Every pattern blends between the material's `albedo` and the color carried by the variant.
+#[derive(Debug, Clone, Copy)]
+pub enum Texture {
+    Solid,
+    Checker { odd: Vec3, scale: f32 },
+    Noise { secondary: Vec3, scale: f32 },
+    Marble { veins: Vec3, scale: f32, turbulence: f32 },
+    Wood { rings: Vec3, scale: f32, turbulence: f32 },
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Material {
+    pub albedo: Vec3,
+    pub kind: u32,
+    pub secondary: Vec3,
+    pub texture: u32,
+    pub scale: f32,
+    pub turbulence: f32,
+    pub normal_map: i32, // layer in the maps texture, -1 when unused
+    pub bump_map: i32,
+    pub bump_strength: f32,
+    _padding: [u32; 3],
+}
+
+unsafe impl Pod for Material {}
+unsafe impl Zeroable for Material {}
+
+impl Material {
+    pub fn diffuse(albedo: Vec3) -> Self {
+        Self::new(DIFFUSE, albedo)
+    }
+
+    pub fn metal(albedo: Vec3) -> Self {
+        Self::new(METAL, albedo)
+    }
+
+    fn new(kind: u32, albedo: Vec3) -> Self {
+        Self {
+            albedo,
+            kind,
+            secondary: albedo,
+            texture: 0,
+            scale: 1.,
+            turbulence: 0.,
+            normal_map: -1,
+            bump_map: -1,
+            bump_strength: 0.,
+            _padding: Default::default(),
+        }
+    }
+
+    pub fn textured(mut self, texture: Texture) -> Self {
+        let (id, secondary, scale, turbulence) = match texture {
+            Texture::Solid => (0, self.albedo, 1., 0.),
+            Texture::Checker { odd, scale } => (1, odd, scale, 0.),
+            Texture::Noise { secondary, scale } => (2, secondary, scale, 0.),
+            Texture::Marble { veins, scale, turbulence } => (3, veins, scale, turbulence),
+            Texture::Wood { rings, scale, turbulence } => (4, rings, scale, turbulence),
+        };
+        self.texture = id;
+        self.secondary = secondary;
+        self.scale = scale;
+        self.turbulence = turbulence;
+        self
+    }
+
+    /// Tangent-space normal map (OpenGL convention, +Y along increasing v).
+    pub fn with_normal_map(mut self, layer: i32) -> Self {
+        self.normal_map = layer;
+        self
+    }
+
+    /// Grayscale height map; `strength` scales the height gradient in texture space.
+    pub fn with_bump_map(mut self, layer: i32, strength: f32) -> Self {
+        self.bump_map = layer;
+        self.bump_strength = strength;
+        self
+    }
+}
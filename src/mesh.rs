@@ -0,0 +1,90 @@
+use std::path::Path;
+
+use ultraviolet::{Vec2, Vec3};
+
+use crate::{material::Material, utils::Triangle};
+
+
+/// Triangles imported from an OBJ file together with the materials and texture maps its MTL refers to.
+/// Material indices stored in the triangles already account for `first_material`.
+pub struct Mesh {
+    pub triangles: Vec<Triangle>,
+    pub materials: Vec<Material>,
+    pub maps: Vec<image::RgbaImage>,
+}
+
+impl Mesh {
+    pub fn load_obj(path: &str, first_material: u32) -> Result<Self, String> {
+        let loading_options = tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        };
+
+        let (models, obj_materials) = tobj::load_obj(path, &loading_options).map_err(|e| e.to_string())?;
+        let model = models.first().ok_or_else(|| format!("{} contains no models", path))?;
+        let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+
+        let mut maps = Vec::new();
+        let mut materials = Vec::new();
+        for obj_material in obj_materials.unwrap_or_default() {
+            let albedo = obj_material.diffuse.unwrap_or([0.7; 3]);
+            let mut material = Material::diffuse(Vec3::new(albedo[0], albedo[1], albedo[2]));
+
+            // `norm` is the de facto key for tangent-space normal maps, `bump`/`map_Bump` holds a height map
+            if let Some(normal_map) = obj_material.unknown_param.get("norm") {
+                let (file, _) = parse_map_statement(normal_map);
+                maps.push(load_map(&base_dir.join(file))?);
+                material = material.with_normal_map(maps.len() as i32 - 1);
+            }
+            if let Some(bump_map) = &obj_material.normal_texture {
+                let (file, strength) = parse_map_statement(bump_map);
+                maps.push(load_map(&base_dir.join(file))?);
+                material = material.with_bump_map(maps.len() as i32 - 1, strength);
+            }
+            materials.push(material);
+        }
+
+        let mesh = &model.mesh;
+        let material = mesh.material_id.map(|id| first_material + id as u32).unwrap_or(0);
+
+        let mut vertices = Vec::with_capacity(mesh.positions.len() / 3);
+        for [x, y, z] in mesh.positions.array_chunks() {
+            vertices.push(Vec3::new(*x, *y, *z));
+        }
+
+        let mut uvs = Vec::with_capacity(mesh.texcoords.len() / 2);
+        for [u, v] in mesh.texcoords.array_chunks() {
+            uvs.push(Vec2::new(*u, *v));
+        }
+
+        let mut triangles = Vec::with_capacity(mesh.indices.len() / 3);
+        for [v1, v2, v3] in mesh.indices.array_chunks() {
+            let (v1, v2, v3) = (*v1 as usize, *v2 as usize, *v3 as usize);
+            let mut triangle = Triangle::new(vertices[v1], vertices[v2], vertices[v3]).with_material(material);
+            if !uvs.is_empty() {
+                triangle = triangle.with_uvs([uvs[v1], uvs[v2], uvs[v3]]);
+            }
+            triangles.push(triangle);
+        }
+
+        Ok(Mesh { triangles, materials, maps })
+    }
+}
+
+/// Splits an MTL map statement such as `-bm 0.5 bumps.png` into the file name and the bump multiplier.
+fn parse_map_statement(statement: &str) -> (&str, f32) {
+    let tokens: Vec<&str> = statement.split_whitespace().collect();
+    let strength = tokens.iter()
+        .position(|token| *token == "-bm")
+        .and_then(|i| tokens.get(i + 1))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1.);
+    (tokens.last().copied().unwrap_or(""), strength)
+}
+
+fn load_map(path: &Path) -> Result<image::RgbaImage, String> {
+    image::open(path)
+        .map(|map| map.to_rgba8())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
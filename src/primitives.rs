@@ -0,0 +1,164 @@
+use bytemuck::{Pod, Zeroable};
+use ultraviolet::{Rotor3, Vec3};
+
+
+/// Infinite plane through `point`. UVs tile every `1 / uv_scale` units.
+/// Like every primitive it moves by `velocity` per frame, which only shows with an open shutter.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Plane {
+    pub point: Vec3,
+    pub material: u32,
+    pub normal: Vec3,
+    pub uv_scale: f32,
+    pub velocity: Vec3,
+    _padding: u32,
+}
+
+/// Parallelogram spanned by the edges `u` and `v` starting at `corner`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Quad {
+    pub corner: Vec3,
+    pub material: u32,
+    pub u: Vec3,
+    _pad0: u32,
+    pub v: Vec3,
+    _pad1: u32,
+    pub velocity: Vec3,
+    _pad2: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Disk {
+    pub center: Vec3,
+    pub material: u32,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub velocity: Vec3,
+    _padding: u32,
+}
+
+/// Box with an arbitrary orientation, `axis_x` and `axis_y` are the first two columns of its rotation.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Cuboid {
+    pub center: Vec3,
+    pub material: u32,
+    pub half_size: Vec3,
+    _pad0: u32,
+    pub axis_x: Vec3,
+    _pad1: u32,
+    pub axis_y: Vec3,
+    _pad2: u32,
+    pub velocity: Vec3,
+    _pad3: u32,
+}
+
+/// Cylinder closed with disks at both ends, `axis` points from the base to the top cap.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Cylinder {
+    pub base: Vec3,
+    pub material: u32,
+    pub axis: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    _pad0: [u32; 3],
+    pub velocity: Vec3,
+    _pad1: u32,
+}
+
+unsafe impl Pod for Plane {}
+unsafe impl Zeroable for Plane {}
+unsafe impl Pod for Quad {}
+unsafe impl Zeroable for Quad {}
+unsafe impl Pod for Disk {}
+unsafe impl Zeroable for Disk {}
+unsafe impl Pod for Cuboid {}
+unsafe impl Zeroable for Cuboid {}
+unsafe impl Pod for Cylinder {}
+unsafe impl Zeroable for Cylinder {}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: u32) -> Self {
+        Self { point, material, normal: normal.normalized(), uv_scale: 1., velocity: Vec3::zero(), _padding: 0 }
+    }
+}
+
+impl Quad {
+    pub fn new(corner: Vec3, u: Vec3, v: Vec3, material: u32) -> Self {
+        Self { corner, material, u, v, velocity: Vec3::zero(), _pad0: 0, _pad1: 0, _pad2: 0 }
+    }
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f32, material: u32) -> Self {
+        Self { center, material, normal: normal.normalized(), radius, velocity: Vec3::zero(), _padding: 0 }
+    }
+}
+
+impl Cuboid {
+    pub fn axis_aligned(min: Vec3, max: Vec3, material: u32) -> Self {
+        Self::oriented((min + max) / 2., (max - min) / 2., Rotor3::identity(), material)
+    }
+
+    pub fn oriented(center: Vec3, half_size: Vec3, rotation: Rotor3, material: u32) -> Self {
+        Self {
+            center,
+            material,
+            half_size,
+            axis_x: rotation * Vec3::unit_x(),
+            axis_y: rotation * Vec3::unit_y(),
+            velocity: Vec3::zero(),
+            _pad0: 0,
+            _pad1: 0,
+            _pad2: 0,
+            _pad3: 0,
+        }
+    }
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3, axis: Vec3, radius: f32, height: f32, material: u32) -> Self {
+        Self {
+            base,
+            material,
+            axis: axis.normalized(),
+            radius,
+            height,
+            velocity: Vec3::zero(),
+            _pad0: Default::default(),
+            _pad1: 0,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Ball;
+    use crate::sdf::Sdf;
+    use crate::csg::Csg;
+    use crate::volume::Volume;
+    use crate::utils::Triangle;
+    use crate::material::Material;
+
+    #[test]
+    fn test_layouts_match_shader() {
+        // Sizes of the corresponding structs in shader.wgsl
+        assert_eq!(std::mem::size_of::<Ball>(), 32);
+        assert_eq!(std::mem::size_of::<Plane>(), 48);
+        assert_eq!(std::mem::size_of::<Quad>(), 64);
+        assert_eq!(std::mem::size_of::<Disk>(), 48);
+        assert_eq!(std::mem::size_of::<Cuboid>(), 80);
+        assert_eq!(std::mem::size_of::<Cylinder>(), 64);
+        assert_eq!(std::mem::size_of::<Sdf>(), 64);
+        assert_eq!(std::mem::size_of::<Csg>(), 144);
+        assert_eq!(std::mem::size_of::<Volume>(), 80);
+        assert_eq!(std::mem::size_of::<Triangle>(), 96);
+        assert_eq!(std::mem::size_of::<Material>(), 64);
+    }
+}
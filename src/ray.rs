@@ -12,7 +12,17 @@ pub struct Ray {
     pub color: Vec3,
     pub screen_x: u32,
     pub screen_y: u32,
-    __pad_2: [u32; 3]
+    // Auxiliary outputs written by the shader, see `Ray` in shader.wgsl
+    pub depth: f32,
+    __pad_2: [u32; 2],
+    pub normal: Vec3,
+    pub material: u32,
+    pub albedo: Vec3,
+    pub object_id: u32,
+    pub primitive_id: u32,
+    /// Set by the shader for every sample, see `RenderSettings::shutter_open`.
+    pub time: f32,
+    __pad_3: [u32; 2],
 }
 
 unsafe impl Pod for Ray {}
@@ -28,7 +38,15 @@ impl Default for Ray {
             color: Vec3::default(),
             screen_x: Default::default(),
             screen_y: Default::default(),
+            depth: 0.,
             __pad_2: Default::default(),
+            normal: Vec3::default(),
+            material: 0,
+            albedo: Vec3::default(),
+            object_id: 0,
+            primitive_id: 0,
+            time: 0.,
+            __pad_3: Default::default(),
         }
     }
 }
@@ -38,7 +56,18 @@ impl Ray {
         let color = color.unwrap_or(Vec3::default());
 
         Ray{
-            orig, dir, screen_x, screen_y, color, __pad_0: 0, __pad_1: 0, __pad_2: Default::default()
+            orig, dir, screen_x, screen_y, color, ..Default::default()
         }
     }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_matches_shader() {
+        assert_eq!(std::mem::size_of::<Ray>(), 112);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,457 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use wgpu::{self, ComputePipeline};
+
+use crate::ray::Ray;
+use crate::scene::{Scene, SceneIterator, SceneChunk};
+use crate::settings::{Budget, RenderSettings};
+use crate::cli::Options;
+use crate::denoise::Denoiser;
+use crate::film::Film;
+use crate::mesh::Mesh;
+use crate::maps::prepare_maps_texture;
+use crate::grid::prepare_grid_texture;
+
+
+/// GPU resources shared by all frames of a render: the pipeline, the mesh with its maps,
+/// the accumulation and splat buffers and the denoiser are only created once.
+pub struct Renderer {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: Arc<ComputePipeline>,
+    mesh: Arc<Mesh>,
+    maps_bg: Arc<wgpu::BindGroup>,
+    accumulation_buffer: Arc<wgpu::Buffer>,
+    splat_buffer: Arc<wgpu::Buffer>,
+    denoiser: Option<Denoiser>,
+}
+
+impl Renderer {
+    /// Frames rendered later must have the dimensions of `scene`.
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>, scene: &Scene, denoise_levels: Option<u32>) -> Self {
+        let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader.wgsl")))
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Main bind group layout"),
+            entries: &[
+                storage_layout_entry(0, false), // rays
+                storage_layout_entry(1, true), // balls
+                uniform_layout_entry(2), // pixel_delta_u
+                uniform_layout_entry(3), // pixel_delta_v
+                storage_layout_entry(4, true), // triangles
+                storage_layout_entry(5, true), // materials
+                storage_layout_entry(6, true), // planes
+                storage_layout_entry(7, true), // quads
+                storage_layout_entry(8, true), // disks
+                storage_layout_entry(9, true), // cuboids
+                storage_layout_entry(10, true), // cylinders
+                storage_layout_entry(11, true), // sdfs
+                storage_layout_entry(12, true), // csgs
+                storage_layout_entry(13, true), // volumes
+                uniform_layout_entry(14), // settings
+                storage_layout_entry(15, false), // accumulation
+                storage_layout_entry(16, false), // splats
+            ],
+        });
+
+        let grid_bg_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Density grid bg layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let maps_bg_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Maps bg layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Compute Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, &grid_bg_layout, &maps_bg_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = Arc::new(device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Compute Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &cs_module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        }));
+
+        let mesh = Arc::new(scene.load_mesh());
+        let maps_bg = Arc::new(prepare_maps_texture(device.clone(), queue.clone(), &pipeline.get_bind_group_layout(2), &mesh.maps));
+
+        // Running sums of every pixel's samples, added to by each pass, see `Accumulator` in the shader
+        let accumulation_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Accumulation buffer"),
+            size: (std::mem::size_of::<[f32; 3]>() as u32 * scene.screen_width * scene.screen_height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+        // Filter weighted color and weight sums the samples splat into, see `splats` in the shader
+        let splat_buffer = Arc::new(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Splat buffer"),
+            size: (std::mem::size_of::<[i32; 4]>() as u32 * scene.screen_width * scene.screen_height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        }));
+
+        let denoiser = denoise_levels.map(|levels| Denoiser::new(device.clone(), queue.clone(), levels));
+
+        Renderer { device, queue, pipeline, mesh, maps_bg, accumulation_buffer, splat_buffer, denoiser }
+    }
+
+    /// Renders `scene` as `frame` of the animation into `filename` and returns the film that was written.
+    pub async fn render(&self, scene: Arc<Scene>, frame: u32, options: &Options, filename: String) -> Result<Film, String> {
+        // Every frame starts accumulating from scratch
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.clear_buffer(&self.accumulation_buffer, 0, None);
+        encoder.clear_buffer(&self.splat_buffer, 0, None);
+        self.queue.submit(Some(encoder.finish()));
+
+        let mut settings = options.settings;
+        settings.width = scene.screen_width;
+        settings.frame = frame;
+
+        let (pixels_stream_sender, pixels_stream_receiver) = tokio::sync::mpsc::channel(20);
+        let (ray_sender, ray_receiver) = tokio::sync::mpsc::channel(20);
+        tokio::spawn(pixel_sender(pixels_stream_sender, scene.clone(), options.budget));
+        tokio::spawn(compute_pixels(pixels_stream_receiver, ray_sender, self.frame_resources(&scene, settings)));
+
+        let aovs = scene.clone().collect_pixels(&filename, ray_receiver, options.save_every, options.save_options, self.denoiser.as_ref()).await?;
+        let film = self.resolve(&scene).await;
+        scene.save_final(&filename, film, aovs.as_ref(), options.save_options, self.denoiser.as_ref()).await
+    }
+
+    /// Uploads `scene` and `settings` for one frame, with ray buffers big enough for a whole tile.
+    fn frame_resources(&self, scene: &Arc<Scene>, settings: RenderSettings) -> FrameResources {
+        let device = self.device.clone();
+        let rays_size = (std::mem::size_of::<Ray>() * (TILE_SIZE * TILE_SIZE) as usize) as wgpu::BufferAddress;
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Staging buffer"),
+            size: rays_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let rays_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Storage buffer"),
+            size: rays_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let settings_buffer = settings.uniform(&device);
+        let grid_bg = prepare_grid_texture(device.clone(), self.queue.clone(), &self.pipeline.get_bind_group_layout(1), scene.density_grid.as_deref());
+
+        let balls_buffer = scene.clone().get_balls_bg(device.clone());
+        let [planes_buffer, quads_buffer, disks_buffer, cuboids_buffer, cylinders_buffer] = scene.clone().get_primitives_bg(device.clone());
+        let (pixel_delta_u_buffer, pixel_delta_v_buffer) = scene.sampling_uniform(device.clone());
+
+        let triangles_buffer = scene.clone().get_triangles_bg(&self.mesh, device.clone());
+        let materials_buffer = scene.clone().get_materials_bg(&self.mesh, device.clone());
+        let sdfs_buffer = scene.clone().get_sdfs_bg(device.clone());
+        let csgs_buffer = scene.clone().get_csgs_bg(device.clone());
+        let volumes_buffer = scene.clone().get_volumes_bg(device.clone());
+        let bind_group_layout: wgpu::BindGroupLayout = self.pipeline.get_bind_group_layout(0);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: rays_buffer.as_entire_binding()
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: balls_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: pixel_delta_u_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: pixel_delta_v_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: triangles_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: materials_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: planes_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: quads_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: disks_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: cuboids_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: cylinders_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: sdfs_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: csgs_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: volumes_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 15,
+                    resource: self.accumulation_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 16,
+                    resource: self.splat_buffer.as_entire_binding(),
+                },
+            ]
+        });
+
+        FrameResources {
+            device,
+            queue: self.queue.clone(),
+            pipeline: self.pipeline.clone(),
+            maps_bg: self.maps_bg.clone(),
+            grid_bg,
+            bind_group,
+            rays_buffer,
+            staging_buffer,
+            settings_buffer,
+            settings,
+        }
+    }
+
+    /// The image from the splat sums once every tile is done. Pixels collected during the render miss
+    /// the samples that tiles dispatched after theirs splatted across the tile edges.
+    async fn resolve(&self, scene: &Scene) -> Film {
+        let size = self.splat_buffer.size();
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Splat staging buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_buffer_to_buffer(&self.splat_buffer, 0, &staging_buffer, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let slice = staging_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.await.unwrap().unwrap();
+
+        let mut film = Film::new(scene.screen_width, scene.screen_height);
+        let data = slice.get_mapped_range();
+        for (index, sums) in bytemuck::cast_slice::<u8, [i32; 4]>(&data).iter().enumerate() {
+            let index = index as u32;
+            film.put(index % scene.screen_width, index / scene.screen_width, splat_mean(*sums));
+        }
+        film
+    }
+}
+
+/// Mirrors `pixel_mean` in the shader: the fixed point scale of the sums cancels out,
+/// and the ratio keeps the sign of a negative weight.
+fn splat_mean([red, green, blue, weight]: [i32; 4]) -> [f32; 3] {
+    if weight == 0 {
+        return [0.; 3];
+    }
+    let weight = weight as f32;
+    [red as f32 / weight, green as f32 / weight, blue as f32 / weight]
+}
+
+/// `render.png` becomes `render.0007.png` for frame 7.
+pub fn frame_path(path: &str, frame: u32) -> String {
+    let path = std::path::Path::new(path);
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("png");
+    path.with_extension(format!("{:04}.{}", frame, extension)).to_string_lossy().into_owned()
+}
+
+fn storage_layout_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn uniform_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// Side of the square tiles the screen is dispatched in, one workgroup per pixel.
+const TILE_SIZE: u32 = 500;
+
+/// Everything `compute_pixels` dispatches a frame's tiles with. The scene buffers and the bind group are
+/// built once per frame, tiles only rewrite `rays_buffer` and are read back through `staging_buffer`.
+struct FrameResources {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: Arc<ComputePipeline>,
+    maps_bg: Arc<wgpu::BindGroup>,
+    grid_bg: wgpu::BindGroup,
+    bind_group: wgpu::BindGroup,
+    rays_buffer: wgpu::Buffer,
+    staging_buffer: wgpu::Buffer,
+    settings_buffer: wgpu::Buffer,
+    settings: RenderSettings,
+}
+
+async fn pixel_sender(pixel_stream: tokio::sync::mpsc::Sender<SceneChunk>, scene: Arc<Scene>, budget: Budget) {
+    let started = std::time::Instant::now();
+    let mut pass = 0;
+    while budget.allows(pass, started.elapsed()) {
+        for mut chunk in SceneIterator::new(&scene, TILE_SIZE, TILE_SIZE).unwrap() {
+            chunk.pass = pass;
+            if pixel_stream.send(chunk).await.is_err() {
+                // Nobody is computing the pixels anymore
+                return;
+            }
+        }
+        pass += 1;
+    }
+}
+
+async fn compute_pixels(
+    mut pixel_stream: tokio::sync::mpsc::Receiver<SceneChunk>,
+    pixels_stream_out: tokio::sync::mpsc::Sender<Vec<Ray>>,
+    frame: FrameResources) {
+    let FrameResources { device, queue, pipeline, maps_bg, grid_bg, bind_group, rays_buffer, staging_buffer, settings_buffer, mut settings } = frame;
+
+    while let Some(chunk) = pixel_stream.recv().await {
+        if chunk.pass != settings.pass_index {
+            settings.pass_index = chunk.pass;
+            queue.write_buffer(&settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+        }
+        let rays_size = (std::mem::size_of::<Ray>() * chunk.len()) as wgpu::BufferAddress;
+        queue.write_buffer(&rays_buffer, 0, bytemuck::cast_slice(chunk.as_ref()));
+
+        let chunk_size = chunk.get_dimensions();
+
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+        cpass.set_pipeline(&pipeline);
+        cpass.set_bind_group(0, &bind_group, &[]);
+        cpass.set_bind_group(1, &grid_bg, &[]);
+        cpass.set_bind_group(2, maps_bg.as_ref(), &[]);
+        cpass.dispatch_workgroups(chunk_size.0, chunk_size.1, 1);
+        drop(cpass);
+
+        encoder.copy_buffer_to_buffer(&rays_buffer, 0, &staging_buffer, 0, rays_size);
+        queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let the_slice = staging_buffer.slice(..rays_size);
+        the_slice.map_async(wgpu::MapMode::Read, move |v| {
+            sender.send(v).unwrap();
+        });
+
+        device.poll(wgpu::Maintain::Wait);
+        if let Ok(()) = receiver.await.unwrap() {
+            let pixels = {
+                let data = the_slice.get_mapped_range();
+                let data = bytemuck::cast_slice::<u8, Ray>(&data);
+                data.to_vec()
+            };
+            // The staging buffer is mapped again for the next tile
+            staging_buffer.unmap();
+            if pixels_stream_out.send(pixels).await.is_err() {
+                break;
+            }
+        }
+
+    }
+    println!("Closing the channel");
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_path() {
+        assert_eq!(frame_path("output.jpg", 7), "output.0007.jpg");
+        assert_eq!(frame_path("renders/output.exr", 120), "renders/output.0120.exr");
+    }
+
+    #[test]
+    fn test_splat_mean() {
+        assert_eq!(splat_mean([1024, 2048, 4096, 2048]), [0.5, 1., 2.]);
+        // Negative lobes may outweigh the positive ones
+        assert_eq!(splat_mean([-1024, -2048, 0, -512]), [2., 4., 0.]);
+        assert_eq!(splat_mean([1, 1, 1, 0]), [0., 0., 0.]);
+    }
+}
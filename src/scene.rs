@@ -1,35 +1,77 @@
 use std::sync::Arc;
 
 use bytemuck::{Pod, Zeroable};
-use ultraviolet::Vec3;
+use ultraviolet::{Rotor3, Vec3};
 use wgpu::util::DeviceExt;
-use crate::{ray::Ray, utils::Triangle};
+use crate::{ray::Ray, mesh::Mesh, material::{Material, Texture}};
+use crate::primitives::{Plane, Quad, Disk, Cuboid, Cylinder};
+use crate::sdf::Sdf;
+use crate::csg::{Csg, CsgOperand};
+use crate::volume::Volume;
+use crate::grid::DensityGrid;
+use crate::film::{Aovs, Film, SaveOptions};
+use crate::denoise::Denoiser;
+use crate::keyframes::ObjectKind;
 
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct Ball {
+    /// Where the ball is at time 0 of the frame.
     pub center: Vec3,
     pub radius: f32,
+    /// Distance covered per frame, blurs the ball when the shutter is open for a while.
+    pub velocity: Vec3,
     pub material: u32,
-    _padding: [u32; 3],
 }
 
 
 unsafe impl Pod for Ball {}
 unsafe impl Zeroable for Ball {}
 
+impl Ball {
+    pub fn new(center: Vec3, radius: f32, material: u32) -> Self {
+        Ball { center, radius, velocity: Vec3::zero(), material }
+    }
+
+    pub fn moving(mut self, velocity: Vec3) -> Self {
+        self.velocity = velocity;
+        self
+    }
+}
+
 
 pub struct Scene {
     pub screen_width: u32,
     pub screen_height: u32,
     pub eye: Vec3,
+    /// Point the camera looks at.
+    pub target: Vec3,
+    /// Vertical field of view in degrees.
+    pub fov: f32,
+    /// Indexed by the objects' `material`, the mesh's own materials follow these.
+    pub materials: Vec<Material>,
+    pub balls: Vec<Ball>,
+    pub planes: Vec<Plane>,
+    pub quads: Vec<Quad>,
+    pub disks: Vec<Disk>,
+    pub cuboids: Vec<Cuboid>,
+    pub cylinders: Vec<Cylinder>,
+    pub sdfs: Vec<Sdf>,
+    pub csgs: Vec<Csg>,
+    pub volumes: Vec<Volume>,
+    pub density_grid: Option<Arc<DensityGrid>>, // shared by every volume created `with_grid`
 }
 
+/// Splits the screen into tiles of rays, row by row. Tiles at the right and bottom edges
+/// are cut down to the pixels that are left, so every pixel is covered exactly once.
 pub struct SceneIterator<'a> {
     scene: &'a Scene,
-    size: usize,
-    stopped: u32,
+    tile_width: u32,
+    tile_height: u32,
+    // Top left corner of the next tile
+    tile_x: u32,
+    tile_y: u32,
     pub pixel00_loc: Vec3,
     pub pixel_delta_u: Vec3,
     pub pixel_delta_v: Vec3,
@@ -41,7 +83,83 @@ impl Default for Scene {
         Scene {
             screen_width: 2000,
             screen_height: 2000,
-            eye: Vec3::new(0f32, 2f32, 1f32)
+            eye: Vec3::new(0f32, 2f32, 1f32),
+            target: Vec3::zero(),
+            fov: 30.,
+            materials: vec![
+                Material::diffuse(Vec3::broadcast(0.7)),
+                Material::metal(Vec3::broadcast(0.7)),
+                Material::diffuse(Vec3::broadcast(0.8)).textured(Texture::Checker {
+                    odd: Vec3::broadcast(0.2),
+                    scale: 3.0,
+                }),
+                Material::diffuse(Vec3::new(0.7, 0.15, 0.1)).textured(Texture::Solid),
+                Material::diffuse(Vec3::new(0.55, 0.6, 0.75)).textured(Texture::Noise {
+                    secondary: Vec3::new(0.15, 0.2, 0.35),
+                    scale: 4.0,
+                }),
+                Material::diffuse(Vec3::new(0.9, 0.9, 0.88)).textured(Texture::Marble {
+                    veins: Vec3::new(0.25, 0.25, 0.3),
+                    scale: 2.0,
+                    turbulence: 5.0,
+                }),
+                Material::diffuse(Vec3::new(0.6, 0.4, 0.2)).textured(Texture::Wood {
+                    rings: Vec3::new(0.35, 0.2, 0.08),
+                    scale: 6.0,
+                    turbulence: 1.0,
+                }),
+            ],
+            balls: vec![
+                Ball::new(Vec3::new(-0.7, 0.0, -1.5), 0.5, 1),
+                // Rolls to the right over an animation, blurred with `--shutter`
+                Ball::new(Vec3::new(1.1, -0.3, 1.8), 0.2, 4).moving(Vec3::new(0.3, 0.0, 0.0)),
+            ],
+            // The ground, where the top of a large ball used to be
+            planes: vec![
+                Plane::new(Vec3::new(0.0, -0.5, 0.0), Vec3::unit_y(), 2),
+            ],
+            quads: vec![
+                Quad::new(Vec3::new(-2.5, -0.5, -3.5), Vec3::new(5.0, 0.0, 0.0), Vec3::new(0.0, 2.5, 0.0), 6),
+            ],
+            disks: vec![
+                Disk::new(Vec3::new(1.6, -0.49, -0.4), Vec3::unit_y(), 0.5, 5),
+            ],
+            cuboids: vec![
+                Cuboid::axis_aligned(Vec3::new(-2.3, -0.5, -2.2), Vec3::new(-1.5, 0.3, -1.4), 4),
+                Cuboid::oriented(Vec3::new(1.7, -0.1, -1.9), Vec3::broadcast(0.4), Rotor3::from_rotation_xz(0.6), 0),
+            ],
+            cylinders: vec![
+                Cylinder::new(Vec3::new(-1.8, -0.5, 0.3), Vec3::unit_y(), 0.25, 0.8, 3),
+            ],
+            // A marble block hollowed out at the top, melting into a ring around its foot
+            sdfs: vec![
+                Sdf::rounded_box(Vec3::new(2.3, -0.15, 1.0), Vec3::broadcast(0.35), 0.05, 5),
+                Sdf::sphere(Vec3::new(2.3, 0.2, 1.0), 0.3, 5).subtract(),
+                Sdf::torus(Vec3::new(2.3, -0.43, 1.0), 0.55, 0.07, 4).smooth_union(0.15),
+            ],
+            csgs: vec![
+                Csg::difference(
+                    CsgOperand::cuboid(Vec3::new(-2.6, -0.1, 1.4), Vec3::broadcast(0.4), Rotor3::from_rotation_xz(0.3)),
+                    CsgOperand::ball(Vec3::new(-2.6, -0.1, 1.4), 0.52),
+                    0,
+                ),
+                Csg::intersection(
+                    CsgOperand::ball(Vec3::new(-1.2, -0.15, 2.2), 0.45),
+                    CsgOperand::cuboid(Vec3::new(-1.2, -0.15, 2.2), Vec3::broadcast(0.35), Rotor3::identity()),
+                    6,
+                ),
+                Csg::union(
+                    CsgOperand::cylinder(Vec3::new(0.3, -0.38, 2.5), Vec3::unit_x(), 0.12, 0.8),
+                    CsgOperand::cylinder(Vec3::new(0.3, -0.38, 2.5), Vec3::unit_z(), 0.12, 0.8),
+                    3,
+                ),
+            ],
+            // A puff of fog behind the monkey and a box of smoke shaped by `density_grid`, uniform without one
+            volumes: vec![
+                Volume::ball(Vec3::new(-0.3, -0.1, -2.6), 0.4, 4.0, Vec3::broadcast(0.8), 0.3),
+                Volume::cuboid(Vec3::new(2.6, -0.5, -0.9), Vec3::new(3.4, 0.6, -0.1), 6.0, Vec3::broadcast(0.9), 0.0).with_grid(),
+            ],
+            density_grid: None,
         }
     }
 
@@ -54,8 +172,72 @@ impl Scene {
         return scene;
     }
 
+    /// The point placing the `index`-th object of the given kind and its velocity, `None` if there is no such object.
+    pub fn motion_mut(&mut self, kind: ObjectKind, index: usize) -> Option<(&mut Vec3, &mut Vec3)> {
+        match kind {
+            ObjectKind::Ball => self.balls.get_mut(index).map(|ball| (&mut ball.center, &mut ball.velocity)),
+            ObjectKind::Plane => self.planes.get_mut(index).map(|plane| (&mut plane.point, &mut plane.velocity)),
+            ObjectKind::Quad => self.quads.get_mut(index).map(|quad| (&mut quad.corner, &mut quad.velocity)),
+            ObjectKind::Disk => self.disks.get_mut(index).map(|disk| (&mut disk.center, &mut disk.velocity)),
+            ObjectKind::Cuboid => self.cuboids.get_mut(index).map(|cuboid| (&mut cuboid.center, &mut cuboid.velocity)),
+            ObjectKind::Cylinder => self.cylinders.get_mut(index).map(|cylinder| (&mut cylinder.base, &mut cylinder.velocity)),
+            ObjectKind::Sdf => self.sdfs.get_mut(index).map(|sdf| (&mut sdf.center, &mut sdf.velocity)),
+            ObjectKind::Volume => self.volumes.get_mut(index).map(|volume| (&mut volume.center, &mut volume.velocity)),
+        }
+    }
+
+    /// Moves every object by its velocity over `frames` frames, so moving objects travel across an animation.
+    pub fn advance(&mut self, frames: f32) {
+        for kind in ObjectKind::ALL {
+            let mut index = 0;
+            while let Some((position, velocity)) = self.motion_mut(kind, index) {
+                *position += *velocity * frames;
+                index += 1;
+            }
+        }
+    }
+
+    /// Turns the `index`-th object of the given kind by `rotation` and grows it by `scale`, both about the point placing it.
+    /// Balls, SDF shapes and volumes can't turn, their shapes are symmetric or axis aligned. `None` if there is no such object.
+    pub fn transform(&mut self, kind: ObjectKind, index: usize, rotation: Rotor3, scale: f32) -> Option<()> {
+        match kind {
+            ObjectKind::Ball => self.balls.get_mut(index)?.radius *= scale,
+            ObjectKind::Plane => {
+                // Planes have no size, their texture grows instead
+                let plane = self.planes.get_mut(index)?;
+                plane.normal = rotation * plane.normal;
+                plane.uv_scale /= scale;
+            },
+            ObjectKind::Quad => {
+                let quad = self.quads.get_mut(index)?;
+                quad.u = rotation * quad.u * scale;
+                quad.v = rotation * quad.v * scale;
+            },
+            ObjectKind::Disk => {
+                let disk = self.disks.get_mut(index)?;
+                disk.normal = rotation * disk.normal;
+                disk.radius *= scale;
+            },
+            ObjectKind::Cuboid => {
+                let cuboid = self.cuboids.get_mut(index)?;
+                cuboid.axis_x = rotation * cuboid.axis_x;
+                cuboid.axis_y = rotation * cuboid.axis_y;
+                cuboid.half_size *= scale;
+            },
+            ObjectKind::Cylinder => {
+                let cylinder = self.cylinders.get_mut(index)?;
+                cylinder.axis = rotation * cylinder.axis;
+                cylinder.radius *= scale;
+                cylinder.height *= scale;
+            },
+            ObjectKind::Sdf => self.sdfs.get_mut(index)?.scale(scale),
+            ObjectKind::Volume => self.volumes.get_mut(index)?.scale(scale),
+        }
+        Some(())
+    }
+
     pub fn sampling_uniform(&self, device: Arc<wgpu::Device>) -> (wgpu::Buffer, wgpu::Buffer) {
-        let self_iter = SceneIterator::new(self, 10000).unwrap();
+        let self_iter = SceneIterator::new(self, 100, 100).unwrap();
 
         let pixel_delta_u_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
@@ -76,106 +258,136 @@ impl Scene {
         (pixel_delta_u_buffer, pixel_delta_v_buffer)
     }
     
-    pub async fn collect_pixels(self: Arc<Self>, filename: String, mut pixels_receiver: tokio::sync::mpsc::Receiver<Vec<Ray>>){
-        let mut image = image::RgbImage::new(self.screen_width, self.screen_height);
-        let total = self.screen_height * self.screen_width;
+    /// Every pass refines all the pixels, so an image is complete whenever `total` pixels have arrived.
+    /// Assembles the pixels of every pass, saving the image every `save_every` passes.
+    /// Returns the first hits' outputs when they are saved or guide the denoiser.
+    pub async fn collect_pixels(self: Arc<Self>, filename: &str, mut pixels_receiver: tokio::sync::mpsc::Receiver<Vec<Ray>>, save_every: u32, save_options: SaveOptions, denoiser: Option<&Denoiser>) -> Result<Option<Aovs>, String> {
+        let mut film = Film::new(self.screen_width, self.screen_height);
+        // The denoiser is guided by the first hits' normals and albedos
+        let mut aovs = (save_options.aovs || denoiser.is_some()).then(|| Aovs::new(self.screen_width, self.screen_height));
+        let total = (self.screen_height * self.screen_width) as usize;
         let mut so_far = 0;
+        let mut passes = 0;
         while let Some(pixels) = pixels_receiver.recv().await {
             for pixel in pixels.iter() {
                 let screen_x = pixel.screen_x;
                 let screen_y = pixel.screen_y;
-                let color = pixel.color.as_array();
-                image.put_pixel(
-                    screen_x, screen_y, image::Rgb([
-                        (color[0] * 255f32) as u8,
-                        (color[1] * 255f32) as u8,
-                        (color[2] * 255f32) as u8,
-                    ]));
+                film.put(screen_x, screen_y, *pixel.color.as_array());
+                if let Some(aovs) = &mut aovs {
+                    aovs.put(pixel);
+                }
             }
             so_far += pixels.len();
-            if so_far % 1000 == 0{
-                // println!("{}", (so_far as f32) / (total as f32));
+            if so_far >= total {
+                so_far -= total;
+                passes += 1;
+                if save_every > 0 && passes % save_every == 0 {
+                    save_film(filename, &film, aovs.as_ref(), denoiser, &save_options).await?;
+                    println!("Zapisano {} po {} przejściach", filename, passes);
+                }
             }
         }
-        image.save(&filename).unwrap();
-        println!("Zapisano {}", &filename);
+        Ok(aovs)
     }
 
-    pub fn get_balls_bg(self: Arc<Self>, cp: Arc<wgpu::ComputePipeline>, device: Arc<wgpu::Device>) -> wgpu::Buffer {
-        let ball = Ball {
-            center: Vec3::new(-0.7, 0.0, -1.5),
-            radius: 0.5,
-            material: 1,
-            _padding: Default::default(),
+    /// Writes the finished `film`, denoised first when there is a denoiser, and returns what was written.
+    pub async fn save_final(&self, filename: &str, film: Film, aovs: Option<&Aovs>, save_options: SaveOptions, denoiser: Option<&Denoiser>) -> Result<Film, String> {
+        let film = match (denoiser, aovs) {
+            (Some(denoiser), Some(aovs)) => denoiser.denoise(&film, aovs).await,
+            _ => film,
         };
+        film.save(filename, &save_options)?;
+        println!("Zapisano {}", filename);
+        if let Some(aovs) = aovs.filter(|_| save_options.aovs) {
+            let aovs_filename = Aovs::path_for(filename);
+            aovs.save(&aovs_filename)?;
+            println!("Zapisano {}", aovs_filename);
+        }
+        Ok(film)
+    }
 
-        let balls: Vec<Ball> = vec![
-            Ball {
-                center: Vec3::new(0.0, -10.5, -1.0),
-                radius: 10.0,
-                material: 1,
-                _padding: Default::default(),
-            }, 
-            // Ball { center: Vec3::new(0.5, 0.0, -0.7) , radius: 0.5, material: 0, _padding: Default::default()}, 
-            // ball
-        ];
-
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Ball's buffer"),
-            contents: bytemuck::cast_slice(&balls),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
-
-        buffer
-
+    pub fn get_balls_bg(self: Arc<Self>, device: Arc<wgpu::Device>) -> wgpu::Buffer {
+        storage_buffer(&device, "Ball's buffer", &self.balls)
     }
 
-    pub fn get_triangles_bg(self: Arc<Self>, cp: Arc<wgpu::ComputePipeline>, device: Arc<wgpu::Device>) -> wgpu::Buffer {
-        let mut loading_options = tobj::LoadOptions::default();
-        loading_options.triangulate = true;
+    /// Buffers for planes, quads, disks, cuboids and cylinders, in the order of their bindings.
+    pub fn get_primitives_bg(self: Arc<Self>, device: Arc<wgpu::Device>) -> [wgpu::Buffer; 5] {
+        [
+            storage_buffer(&device, "Planes buffer", &self.planes),
+            storage_buffer(&device, "Quads buffer", &self.quads),
+            storage_buffer(&device, "Disks buffer", &self.disks),
+            storage_buffer(&device, "Cuboids buffer", &self.cuboids),
+            storage_buffer(&device, "Cylinders buffer", &self.cylinders),
+        ]
+    }
 
-        let model = tobj::load_obj("monkey2.obj", &loading_options).unwrap().0;
-        let model = model.get(0).unwrap();
+    pub fn get_sdfs_bg(self: Arc<Self>, device: Arc<wgpu::Device>) -> wgpu::Buffer {
+        storage_buffer(&device, "SDF buffer", &self.sdfs)
+    }
 
-        let mut vertices = Vec::with_capacity(1000);
-        let mut triangles = Vec::with_capacity(1000);
+    pub fn get_csgs_bg(self: Arc<Self>, device: Arc<wgpu::Device>) -> wgpu::Buffer {
+        storage_buffer(&device, "CSG buffer", &self.csgs)
+    }
 
+    pub fn get_volumes_bg(self: Arc<Self>, device: Arc<wgpu::Device>) -> wgpu::Buffer {
+        storage_buffer(&device, "Volumes buffer", &self.volumes)
+    }
 
-        for [x, y, z] in model.mesh.positions.array_chunks() {
-            vertices.push(
-                Vec3::new(x.clone(), y.clone(), z.clone())
-            )
-        }
+    pub fn load_mesh(&self) -> Mesh {
+        Mesh::load_obj("monkey2.obj", self.materials.len() as u32).unwrap()
+    }
 
-        for [v1, v2, v3] in model.mesh.indices.array_chunks() {
-            triangles.push(
-                Triangle::new(
-                    vertices[v1.clone() as usize], 
-                    vertices[v2.clone() as usize],
-                    vertices[v3.clone() as usize]
-            )
-        )
-        }
+    pub fn get_materials_bg(self: Arc<Self>, mesh: &Mesh, device: Arc<wgpu::Device>) -> wgpu::Buffer {
+        let mut materials = self.materials.clone();
+        materials.extend_from_slice(&mesh.materials);
 
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Buffer with triangles"),
-            contents: bytemuck::cast_slice(&triangles),
+            label: Some("Materials buffer"),
+            contents: bytemuck::cast_slice(&materials),
             usage: wgpu::BufferUsages::STORAGE,
         });
 
         buffer
     }
+
+    pub fn get_triangles_bg(self: Arc<Self>, mesh: &Mesh, device: Arc<wgpu::Device>) -> wgpu::Buffer {
+        storage_buffer(&device, "Buffer with triangles", &mesh.triangles)
+    }
+}
+
+
+/// Writes the film, denoised first when there is a denoiser.
+async fn save_film(filename: &str, film: &Film, aovs: Option<&Aovs>, denoiser: Option<&Denoiser>, save_options: &SaveOptions) -> Result<(), String> {
+    match (denoiser, aovs) {
+        (Some(denoiser), Some(aovs)) => denoiser.denoise(film, aovs).await.save(filename, save_options),
+        _ => film.save(filename, save_options),
+    }
+}
+
+/// A binding can't be empty, so an empty list is uploaded as a single zeroed element.
+/// Zero-sized primitives are never reported as hits by the shader.
+fn storage_buffer<T: Pod>(device: &wgpu::Device, label: &str, items: &[T]) -> wgpu::Buffer {
+    let zeroed = [T::zeroed()];
+    let items = if items.is_empty() { &zeroed[..] } else { items };
+
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: bytemuck::cast_slice(items),
+        usage: wgpu::BufferUsages::STORAGE,
+    })
 }
 
 
 impl<'a> SceneIterator<'a> {
-    pub fn new(scene: &'a Scene, size: usize) -> Result<Self, String> {
-        let sqrt = (size as f32).sqrt();
+    pub fn new(scene: &'a Scene, tile_width: u32, tile_height: u32) -> Result<Self, String> {
+        if tile_width == 0 || tile_height == 0 {
+            return Err(String::from("Tiles must be at least one pixel wide and high"));
+        }
 
         let vup = Vec3::new(0., 1., 0.);
         let look_from = scene.eye;
-        let look_at = Vec3::new(0., 0., 0.);
-        let fov = 30.0f32;
+        let look_at = scene.target;
+        let fov = scene.fov;
         let focal_length = (look_at - look_from).dot(look_at - look_from).sqrt();
         let h = (fov.to_radians() / 2.).tan();
 
@@ -194,17 +406,13 @@ impl<'a> SceneIterator<'a> {
         let pixel_delta_v = viewport_v / (scene.screen_height as f32);
 
         let viewport_upper_left_corner = camera_center - focal_length * w - viewport_u / 2. - viewport_v / 2.;
-        let pixel00_loc = viewport_upper_left_corner;
+        let pixel00_loc = viewport_upper_left_corner + (pixel_delta_u + pixel_delta_v) / 2.;
 
-        if (sqrt - sqrt.round()) == 0f32 {
-            Ok(SceneIterator {
-                scene, size, pixel00_loc, pixel_delta_u, pixel_delta_v,
-                stopped: 0,
-            })
-        }
-        else {
-            Err(String::from("`size` must be a square of some number"))
-        }
+        Ok(SceneIterator {
+            scene, tile_width, tile_height, pixel00_loc, pixel_delta_u, pixel_delta_v,
+            tile_x: 0,
+            tile_y: 0,
+        })
     }
 }
 
@@ -212,56 +420,55 @@ impl<'a> Iterator for SceneIterator<'a> {
     type Item = SceneChunk;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.stopped >= self.scene.screen_width * self.scene.screen_height {
+        if self.tile_y >= self.scene.screen_height || self.scene.screen_width == 0 {
             return None
         }
-        
-        let mut rays: Vec<Ray> = Vec::with_capacity(self.size);
-        for pixel_id in self.stopped..(self.scene.screen_width * self.scene.screen_height) {
-            let screen_y = pixel_id.div_floor(self.scene.screen_width);
-
-            let screen_x = pixel_id - self.scene.screen_width * screen_y;
-            let (screen_x, screen_y) = (screen_x as f32, screen_y as f32);
-
-            let pixel_center = self.pixel00_loc + (self.pixel_delta_u * screen_x) + (self.pixel_delta_v * screen_y);
-            let ray = Ray::new(
-                self.scene.eye.clone(),
-                pixel_center - self.scene.eye,
-                None,
-                screen_x as u32,
-                screen_y as u32,
-            );
-            
-            rays.push(ray);
-            if rays.len() >= self.size {
-                self.stopped = pixel_id + 1;
-                return Some(SceneChunk::from_vec(rays, self.size));
-            }
 
+        let (tile_x, tile_y) = (self.tile_x, self.tile_y);
+        let width = self.tile_width.min(self.scene.screen_width - tile_x);
+        let height = self.tile_height.min(self.scene.screen_height - tile_y);
+
+        // Row by row, the order the shader indexes the rays of a dispatch in
+        let mut rays: Vec<Ray> = Vec::with_capacity((width * height) as usize);
+        for screen_y in tile_y..tile_y + height {
+            for screen_x in tile_x..tile_x + width {
+                let pixel_center = self.pixel00_loc + (self.pixel_delta_u * screen_x as f32) + (self.pixel_delta_v * screen_y as f32);
+                rays.push(Ray::new(
+                    self.scene.eye,
+                    pixel_center - self.scene.eye,
+                    None,
+                    screen_x,
+                    screen_y,
+                ));
+            }
         }
-        Some(SceneChunk::from_vec(rays, self.size))
 
+        self.tile_x += self.tile_width;
+        if self.tile_x >= self.scene.screen_width {
+            self.tile_x = 0;
+            self.tile_y += self.tile_height;
+        }
+        Some(SceneChunk::from_vec(rays, width, height))
     }
 }
 
+/// Rays of a `width` by `height` tile, dispatched as one workgroup per ray.
 pub struct SceneChunk {
     data: Vec<Ray>,
-    size: usize,
+    width: u32,
+    height: u32,
+    pub pass: u32,
 }
 
 impl SceneChunk {
-    pub fn from_vec(data: Vec<Ray>, size: usize) -> Self {
+    pub fn from_vec(data: Vec<Ray>, width: u32, height: u32) -> Self {
+        debug_assert_eq!(data.len(), (width * height) as usize);
         SceneChunk {
-            data, size
+            data, width, height, pass: 0
         }
     }
     pub fn get_dimensions(&self) -> (u32, u32) {
-        if self.size == self.data.len() {
-            let sqrt = (self.size as f32).sqrt() as u32;
-            (sqrt, sqrt)
-        } else {
-            (self.size as u32, 1)
-        }
+        (self.width, self.height)
     }
     pub fn len(&self) -> usize {
         self.data.len()
@@ -281,9 +488,32 @@ mod tests {
 
     #[test]
     fn test_scene_iterator() {
-        let scene = Scene { lu: Default::default(), width: 1., height: 1., screen_width: 10, screen_height: 10, eye: Default::default() };
-        let iterator = SceneIterator::new(&scene, 25).unwrap();
+        let scene = Scene { screen_width: 10, screen_height: 10, ..Default::default() };
+        assert_eq!(SceneIterator::new(&scene, 5, 5).unwrap().count(), 4);
+        assert!(SceneIterator::new(&scene, 0, 5).is_err());
+
+        // Tiles that don't divide the screen are cut down at its right and bottom edges
+        let chunks: Vec<SceneChunk> = SceneIterator::new(&scene, 4, 3).unwrap().collect();
+        assert_eq!(chunks.len(), 12);
+        assert_eq!(chunks[0].get_dimensions(), (4, 3));
+        assert_eq!(chunks[2].get_dimensions(), (2, 3));
+        assert_eq!(chunks[11].get_dimensions(), (2, 1));
+
+        let mut covered = vec![0; 100];
+        for chunk in &chunks {
+            let (width, height) = chunk.get_dimensions();
+            assert_eq!(chunk.len(), (width * height) as usize);
+            let first = &chunk.as_ref()[0];
+            for (i, ray) in chunk.as_ref().iter().enumerate() {
+                // The shader's index of the ray in a dispatch of width by height workgroups
+                assert_eq!((ray.screen_x - first.screen_x, ray.screen_y - first.screen_y), (i as u32 % width, i as u32 / width));
+                covered[(ray.screen_y * 10 + ray.screen_x) as usize] += 1;
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
 
-        assert_eq!(iterator.into_iter().collect::<Vec<_>>().len(), 4);
+        let larger_than_screen = SceneIterator::new(&scene, 64, 64).unwrap().collect::<Vec<_>>();
+        assert_eq!(larger_than_screen.len(), 1);
+        assert_eq!(larger_than_screen[0].get_dimensions(), (10, 10));
     }
 }
\ No newline at end of file
@@ -0,0 +1,86 @@
+use bytemuck::{Pod, Zeroable};
+use ultraviolet::Vec3;
+
+
+// Shapes, kind 0 marks the placeholder of an empty list and is skipped by the shader
+const SPHERE: u32 = 1;
+const TORUS: u32 = 2;
+const ROUNDED_BOX: u32 = 3;
+
+// Operators combining a shape with everything listed before it
+const UNION: u32 = 0;
+const SUBTRACT: u32 = 1;
+const SMOOTH_UNION: u32 = 2;
+
+/// Signed distance field shape, sphere traced in the shader.
+/// The scene's field is built by folding `Scene::sdfs` in order with each shape's operator.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Sdf {
+    pub center: Vec3,
+    kind: u32,
+    params: Vec3, // sphere - radius in x, torus - major and minor radius, box - half size
+    op: u32,
+    pub blend: f32,
+    pub rounding: f32,
+    pub material: u32,
+    _pad0: u32,
+    /// Distance covered per frame, the shape moves through the field when the shutter is open.
+    pub velocity: Vec3,
+    _pad1: u32,
+}
+
+unsafe impl Pod for Sdf {}
+unsafe impl Zeroable for Sdf {}
+
+impl Sdf {
+    pub fn sphere(center: Vec3, radius: f32, material: u32) -> Self {
+        Self::new(SPHERE, center, Vec3::new(radius, 0., 0.), material)
+    }
+
+    /// Torus lying in the XZ plane.
+    pub fn torus(center: Vec3, major_radius: f32, minor_radius: f32, material: u32) -> Self {
+        Self::new(TORUS, center, Vec3::new(major_radius, minor_radius, 0.), material)
+    }
+
+    pub fn rounded_box(center: Vec3, half_size: Vec3, rounding: f32, material: u32) -> Self {
+        let mut sdf = Self::new(ROUNDED_BOX, center, half_size, material);
+        sdf.rounding = rounding;
+        sdf
+    }
+
+    fn new(kind: u32, center: Vec3, params: Vec3, material: u32) -> Self {
+        Self {
+            center,
+            kind,
+            params,
+            op: UNION,
+            blend: 0.,
+            rounding: 0.,
+            material,
+            _pad0: 0,
+            velocity: Vec3::zero(),
+            _pad1: 0,
+        }
+    }
+
+    /// Grows the shape about its center, along with its rounding and blend.
+    pub fn scale(&mut self, factor: f32) {
+        self.params *= factor;
+        self.rounding *= factor;
+        self.blend *= factor;
+    }
+
+    /// Carves this shape out of the shapes listed before it.
+    pub fn subtract(mut self) -> Self {
+        self.op = SUBTRACT;
+        self
+    }
+
+    /// Blends with the shapes listed before it over roughly `blend` units, a plain union for `blend` of 0.
+    pub fn smooth_union(mut self, blend: f32) -> Self {
+        self.op = SMOOTH_UNION;
+        self.blend = blend;
+        self
+    }
+}
@@ -0,0 +1,156 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+
+/// Quality knobs read by the shader from a uniform buffer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    pub samples: u32,
+    pub max_depth: u32,
+    /// Paths whose throughput drops below this play Russian roulette, surviving with probability
+    /// `throughput / roulette_threshold`. 0 disables it.
+    pub roulette_threshold: f32,
+    /// Row length of the accumulation buffer.
+    pub width: u32,
+    /// Pixels whose relative standard error of luminance falls below this stop being sampled, 0 disables it.
+    pub adaptive_threshold: f32,
+    /// Samples a pixel needs before its variance estimate is trusted.
+    pub adaptive_min_samples: u32,
+    /// Index of the progressive pass being rendered, part of the shader's random number key.
+    pub pass_index: u32,
+    /// Global seed of the shader's random numbers, renders with the same seed are reproducible.
+    pub seed: u32,
+    /// `Sampler` generating the pixel and scattering samples.
+    pub sampling: u32,
+    /// `Filter` weighting the samples of a pixel.
+    pub pixel_filter: u32,
+    /// Half width of the filter's footprint in pixels.
+    pub filter_radius: f32,
+    /// Bounces every path takes before Russian roulette may terminate it.
+    pub roulette_depth: u32,
+    /// Every sample sees the scene at a random time between these, in frames relative to the rendered one.
+    /// Objects moving meanwhile are blurred, an instant shutter (the default) renders them sharp.
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+    /// Frame of the animation being rendered, folded into the seed so that frames don't share their noise.
+    pub frame: u32,
+}
+
+unsafe impl Pod for RenderSettings {}
+unsafe impl Zeroable for RenderSettings {}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            samples: 4,
+            max_depth: 5,
+            roulette_threshold: 0.,
+            width: 0,
+            adaptive_threshold: 0.,
+            adaptive_min_samples: 16,
+            pass_index: 0,
+            seed: 0,
+            sampling: Sampler::Independent as u32,
+            pixel_filter: Filter::Box as u32,
+            filter_radius: Filter::Box.default_radius(),
+            roulette_depth: 3,
+            shutter_open: 0.,
+            shutter_close: 0.,
+            frame: 0,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn uniform(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Render settings buffer"),
+            contents: bytemuck::cast_slice(&[*self]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+}
+
+/// Sequences the shader draws pixel and scattering samples from, values match `SAMPLER_*` in the shader.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampler {
+    /// Hashed white noise.
+    Independent = 0,
+    /// Owen scrambled Sobol points, decorrelated per pixel.
+    Sobol = 1,
+    /// Sobol points shared by all pixels and shifted per pixel by interleaved gradient noise,
+    /// which spreads the error between neighbouring pixels at high frequencies.
+    Ign = 2,
+}
+
+impl FromStr for Sampler {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "independent" => Ok(Sampler::Independent),
+            "sobol" => Ok(Sampler::Sobol),
+            "ign" => Ok(Sampler::Ign),
+            _ => Err(format!("Unknown sampler {}", name)),
+        }
+    }
+}
+
+/// Reconstruction filters, values match `FILTER_*` in the shader.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Box = 0,
+    Tent = 1,
+    Gaussian = 2,
+    /// Mitchell-Netravali with B = C = 1/3.
+    Mitchell = 3,
+}
+
+impl Filter {
+    pub fn default_radius(&self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            _ => Err(format!("Unknown filter {}", name)),
+        }
+    }
+}
+
+/// When progressive rendering stops adding passes to the accumulation buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    pub passes: u32,
+    pub time: Option<Duration>,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget { passes: 1, time: None }
+    }
+}
+
+impl Budget {
+    /// Whether pass number `pass` (counting from 0) may start after `elapsed` time of rendering.
+    pub fn allows(&self, pass: u32, elapsed: Duration) -> bool {
+        pass < self.passes && self.time.is_none_or(|time| elapsed < time)
+    }
+}
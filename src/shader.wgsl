@@ -4,20 +4,187 @@ struct Ray {
     color: vec3<f32>,
     screen_x: u32,
     screen_y: u32,
+    // Auxiliary outputs of the first hit through the pixel's center
+    depth: f32, // distance to the hit, 0 for misses
+    normal: vec3<f32>, // world space shading normal
+    material: u32, // NO_MATERIAL for misses
+    albedo: vec3<f32>,
+    object_id: u32, // OBJECT_* kind of the hit object
+    primitive_id: u32, // index of the object among the ones of its kind
+    time: f32, // within the shutter interval, moving objects are hit where they are at this time
+}
+
+struct RenderSettings {
+    samples: u32,
+    max_depth: u32,
+    roulette_threshold: f32, // paths with lower throughput play Russian roulette, 0 disables it
+    width: u32, // row length of `accumulation` and `splats`
+    adaptive_threshold: f32, // relative error of a converged pixel, 0 disables adaptive sampling
+    adaptive_min_samples: u32,
+    pass_index: u32, // index of the progressive pass, keys the random numbers
+    seed: u32,
+    sampling: u32, // SAMPLER_* generating the pixel and scattering samples
+    pixel_filter: u32, // FILTER_* reconstructing the pixel from its samples
+    filter_radius: f32, // in pixels
+    roulette_depth: u32, // bounces before Russian roulette starts
+    shutter_open: f32, // in frames, relative to the frame being rendered
+    shutter_close: f32,
+    frame: u32, // of the animation, keys the random numbers so noise changes between frames
+}
+
+// Statistics of the samples taken by a pixel itself, their colors are splatted into `splats`
+struct Accumulator {
+    samples: f32,
+    luminance: f32, // sum of the samples' luminance and of its squares, for the variance estimate
+    luminance_sq: f32,
 }
 
 struct Ball { // object type 1.0
-    center: vec3<f32>,
+    center: vec3<f32>, // at time 0
     radius: f32,
+    velocity: vec3<f32>, // per frame
     material: u32,
 }
 
+struct Material {
+    albedo: vec3<f32>,
+    kind: u32, // 0 - diffuse, 1 - metal
+    secondary: vec3<f32>,
+    texture: u32, // 0 - solid, 1 - checker, 2 - noise, 3 - marble, 4 - wood
+    scale: f32,
+    turbulence: f32,
+    normal_map: i32, // layer in `maps`, -1 when unused
+    bump_map: i32,
+    bump_strength: f32,
+}
+
 struct Triangle { // object type 2.0
     v0: vec3<f32>,
+    material: u32,
     v1: vec3<f32>,
     v2: vec3<f32>,
+    tangent: vec4<f32>, // w - handedness of the bitangent
+    uv0: vec2<f32>,
+    uv1: vec2<f32>,
+    uv2: vec2<f32>,
+}
+
+struct Plane { // object type 3.0
+    point: vec3<f32>,
+    material: u32,
+    normal: vec3<f32>,
+    uv_scale: f32,
+    velocity: vec3<f32>, // per frame
+}
+
+struct Quad { // object type 4.0
+    corner: vec3<f32>,
+    material: u32,
+    u: vec3<f32>,
+    v: vec3<f32>,
+    velocity: vec3<f32>,
+}
+
+struct Disk { // object type 5.0
+    center: vec3<f32>,
+    material: u32,
+    normal: vec3<f32>,
+    radius: f32,
+    velocity: vec3<f32>,
+}
+
+struct Cuboid { // object type 6.0
+    center: vec3<f32>,
+    material: u32,
+    half_size: vec3<f32>,
+    axis_x: vec3<f32>,
+    axis_y: vec3<f32>,
+    velocity: vec3<f32>,
+}
+
+struct Cylinder { // object type 7.0
+    base: vec3<f32>,
+    material: u32,
+    axis: vec3<f32>,
+    radius: f32,
+    height: f32,
+    velocity: vec3<f32>,
+}
+
+struct Sdf { // object type 8.0
+    center: vec3<f32>,
+    kind: u32, // 0 - placeholder, 1 - sphere, 2 - torus, 3 - rounded box
+    params: vec3<f32>,
+    op: u32, // 0 - union, 1 - subtract, 2 - smooth union
+    blend: f32,
+    rounding: f32,
+    material: u32,
+    velocity: vec3<f32>,
+}
+
+struct CsgOperand {
+    center: vec3<f32>,
+    kind: u32, // 0 - placeholder, 1 - ball, 2 - cuboid, 3 - cylinder
+    size: vec3<f32>, // ball - radius in x, cuboid - half size, cylinder - radius and height
+    axis_x: vec3<f32>,
+    axis_y: vec3<f32>,
+}
+
+struct Csg { // object type 9.0
+    a: CsgOperand,
+    b: CsgOperand,
+    op: u32, // 0 - union, 1 - intersection, 2 - difference
+    material: u32,
+}
+
+// Part of the ray inside a convex shape, empty when t_in > t_out
+struct Interval {
+    t_in: f32,
+    t_out: f32,
+    n_in: vec3<f32>,
+    n_out: vec3<f32>,
+}
+
+struct Volume {
+    center: vec3<f32>,
+    shape: u32, // 0 - placeholder, 1 - ball, 2 - axis aligned box
+    size: vec3<f32>, // ball - radius in x, box - half size
+    density: f32,
+    albedo: vec3<f32>,
+    anisotropy: f32, // Henyey-Greenstein g
+    heterogeneous: u32, // 1 - density is modulated by `density_grid`
+    velocity: vec3<f32>,
 }
 
+struct Scatter {
+    t: f32, // -1 when the ray passes through all the media
+    albedo: vec3<f32>,
+    anisotropy: f32,
+}
+
+struct Hit {
+    t: f32, // -1 when nothing was hit
+    material: u32,
+    normal: vec3<f32>,
+    uv: vec2<f32>,
+    tangent: vec4<f32>,
+    object: u32,
+    primitive: u32,
+}
+
+const OBJECT_NONE: u32 = 0u;
+const OBJECT_BALL: u32 = 1u;
+const OBJECT_TRIANGLE: u32 = 2u;
+const OBJECT_PLANE: u32 = 3u;
+const OBJECT_QUAD: u32 = 4u;
+const OBJECT_DISK: u32 = 5u;
+const OBJECT_CUBOID: u32 = 6u;
+const OBJECT_CYLINDER: u32 = 7u;
+const OBJECT_SDF: u32 = 8u;
+const OBJECT_CSG: u32 = 9u;
+
+const NO_MATERIAL: u32 = 0xffffffffu;
+
 
 @group(0)
 @binding(0)
@@ -40,9 +207,68 @@ var<uniform> pixel_delta_v: vec3<f32>;
 @binding(4)
 var<storage> triangles: array<Triangle>;
 
+@group(0)
+@binding(5)
+var<storage> materials: array<Material>;
+
+@group(0)
+@binding(6)
+var<storage> planes: array<Plane>;
+
+@group(0)
+@binding(7)
+var<storage> quads: array<Quad>;
+
+@group(0)
+@binding(8)
+var<storage> disks: array<Disk>;
+
+@group(0)
+@binding(9)
+var<storage> cuboids: array<Cuboid>;
+
+@group(0)
+@binding(10)
+var<storage> cylinders: array<Cylinder>;
+
+@group(0)
+@binding(11)
+var<storage> sdfs: array<Sdf>;
+
+@group(0)
+@binding(12)
+var<storage> csgs: array<Csg>;
+
+@group(0)
+@binding(13)
+var<storage> volumes: array<Volume>;
+
+@group(0)
+@binding(14)
+var<uniform> settings: RenderSettings;
+
+@group(0)
+@binding(15)
+var<storage, read_write> accumulation: array<Accumulator>;
+
+// Four per pixel: the red, green and blue sums of the samples splatted into it weighted by the
+// reconstruction filter, and the sum of the weights. Stored in fixed point, see `splat_add`
+@group(0)
+@binding(16)
+var<storage, read_write> splats: array<atomic<i32>>;
+
 @group(1) @binding(0)
-var noise_texture: texture_2d<f32>;
+var density_grid: texture_3d<f32>;
+
+@group(2) @binding(0)
+var maps: texture_2d_array<f32>;
 
+@group(2) @binding(1)
+var maps_sampler: sampler;
+
+
+const PI: f32 = 3.14159265358979;
+const MIN_T: f32 = 0.001;
 
 fn pcg(v: u32) -> u32 {
     var seed = (v ^ 61u) ^ (v >> 16u);
@@ -53,19 +279,222 @@ fn pcg(v: u32) -> u32 {
     return seed;
 }
 
-fn prng (p: f32) -> f32 {
-  return f32(pcg(u32(p))) / f32(0xffffffffu);
+// PCG hash from "Hash Functions for GPU Rendering" (Jarzynski, Olano)
+fn pcg_hash(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Counter-based random numbers: every draw hashes a key identifying the pixel, pass and sample
+// together with the index of the draw, so no state has to be carried between invocations
+var<private> rng_key: u32;
+var<private> rng_counter: u32;
+var<private> rng_pixel: vec2<u32>;
+var<private> rng_pixel_key: u32;
+var<private> rng_sample: u32;
+
+// Draws reserved for each bounce, the ones before the first bounce belong to the camera
+const RNG_BOUNCE_STRIDE: u32 = 1024u;
+
+const SAMPLER_INDEPENDENT: u32 = 0u;
+const SAMPLER_SOBOL: u32 = 1u;
+const SAMPLER_IGN: u32 = 2u;
+
+fn frame_seed() -> u32 {
+    return pcg_hash(settings.seed ^ pcg_hash(settings.frame));
+}
+
+// `sample` counts the pixel's samples over all passes, it indexes the low discrepancy sequences
+fn rng_init(pixel: vec2<u32>, pass_index: u32, sample: u32) {
+    rng_pixel = pixel;
+    rng_pixel_key = pcg_hash(pixel.x ^ pcg_hash(pixel.y ^ frame_seed()));
+    rng_sample = sample;
+    rng_key = pcg_hash(rng_pixel_key ^ pcg_hash(pass_index ^ pcg_hash(sample)));
+    rng_counter = 0u;
 }
 
-fn triangle_hit(ray: Ray, triangle_id: u32) -> vec4<f32> {
+fn rng_start_bounce(bounce: u32) {
+    rng_counter = (bounce + 1u) * RNG_BOUNCE_STRIDE;
+}
+
+fn to_unit_float(value: u32) -> f32 {
+    return f32(value >> 8u) / 16777216.;
+}
+
+// Uniform in [0, 1)
+fn random() -> f32 {
+    let value = pcg_hash(rng_key ^ pcg_hash(rng_counter));
+    rng_counter += 1u;
+    return to_unit_float(value);
+}
+
+// Owen scrambling by hashing, from "Practical Hash-based Owen Scrambling" (Burley)
+fn laine_karras_permutation(value: u32, seed: u32) -> u32 {
+    var x = value + seed;
+    x ^= x * 0x6c50b47cu;
+    x ^= x * 0xb82f1e52u;
+    x ^= x * 0xc7afe638u;
+    x ^= x * 0x8d22f6e6u;
+    return x;
+}
+
+fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    return reverseBits(laine_karras_permutation(reverseBits(value), seed));
+}
+
+// Second Sobol dimension, the first one is the bit reversed index
+fn sobol_second_dimension(index: u32) -> u32 {
+    var direction = 1u << 31u;
+    var result = 0u;
+    var i = index;
+    while i != 0u {
+        if (i & 1u) != 0u {
+            result ^= direction;
+        }
+        direction ^= direction >> 1u;
+        i >>= 1u;
+    }
+    return result;
+}
+
+// Owen scrambled 2D Sobol point, pairs of dimensions are decorrelated by shuffling the index with their own seed
+fn sobol_2d(index: u32, seed: u32) -> vec2<u32> {
+    let shuffled = nested_uniform_scramble(index, seed);
+    return vec2<u32>(
+        nested_uniform_scramble(reverseBits(shuffled), pcg_hash(seed ^ 0x9e3779b9u)),
+        nested_uniform_scramble(sobol_second_dimension(shuffled), pcg_hash(seed ^ 0x7f4a7c15u)),
+    );
+}
+
+// Interleaved gradient noise (Jimenez): neighbouring pixels get well spread offsets
+fn interleaved_gradient_noise(pixel: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(pixel, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+// Two dimensions of the current sample from the sampler selected in the settings
+fn sample_2d() -> vec2<f32> {
+    let dimension = rng_counter;
+    rng_counter += 2u;
+
+    switch settings.sampling {
+        case SAMPLER_SOBOL: {
+            let point = sobol_2d(rng_sample, pcg_hash(rng_pixel_key ^ pcg_hash(dimension)));
+            return vec2<f32>(to_unit_float(point.x), to_unit_float(point.y));
+        }
+        // Every pixel shares the same sequence, shifted toroidally by interleaved gradient noise,
+        // so the error between neighbouring pixels is spread at high frequencies
+        case SAMPLER_IGN: {
+            let point = sobol_2d(rng_sample, pcg_hash(dimension ^ frame_seed()));
+            let pixel = vec2<f32>(rng_pixel) + 5.588238 * f32(dimension / 2u);
+            let shift = vec2<f32>(interleaved_gradient_noise(pixel), interleaved_gradient_noise(pixel.yx + 17.));
+            return fract(vec2<f32>(to_unit_float(point.x), to_unit_float(point.y)) + shift);
+        }
+        default: {
+            let key = rng_key;
+            return vec2<f32>(
+                to_unit_float(pcg_hash(key ^ pcg_hash(dimension))),
+                to_unit_float(pcg_hash(key ^ pcg_hash(dimension + 1u))),
+            );
+        }
+    }
+}
+
+fn lattice_gradient(cell: vec3<f32>) -> vec3<f32> {
+    let c = vec3<u32>(vec3<i32>(cell));
+    let h = pcg(c.x ^ pcg(c.y ^ pcg(c.z))) % 12u;
+    // Gradients of the improved Perlin noise: midpoints of the cube edges
+    switch h {
+        case 0u: { return vec3<f32>(1., 1., 0.); }
+        case 1u: { return vec3<f32>(-1., 1., 0.); }
+        case 2u: { return vec3<f32>(1., -1., 0.); }
+        case 3u: { return vec3<f32>(-1., -1., 0.); }
+        case 4u: { return vec3<f32>(1., 0., 1.); }
+        case 5u: { return vec3<f32>(-1., 0., 1.); }
+        case 6u: { return vec3<f32>(1., 0., -1.); }
+        case 7u: { return vec3<f32>(-1., 0., -1.); }
+        case 8u: { return vec3<f32>(0., 1., 1.); }
+        case 9u: { return vec3<f32>(0., -1., 1.); }
+        case 10u: { return vec3<f32>(0., 1., -1.); }
+        default: { return vec3<f32>(0., -1., -1.); }
+    }
+}
+
+fn perlin(p: vec3<f32>) -> f32 {
+    let cell = floor(p);
+    let f = p - cell;
+    let u = f * f * f * (f * (f * 6. - 15.) + 10.);
+
+    var corners: array<f32, 8>;
+    for (var i: u32 = 0u; i < 8u; i++) {
+        let corner = vec3<f32>(f32(i & 1u), f32((i >> 1u) & 1u), f32((i >> 2u) & 1u));
+        corners[i] = dot(lattice_gradient(cell + corner), f - corner);
+    }
+
+    let x0 = mix(corners[0], corners[1], u.x);
+    let x1 = mix(corners[2], corners[3], u.x);
+    let x2 = mix(corners[4], corners[5], u.x);
+    let x3 = mix(corners[6], corners[7], u.x);
+    return mix(mix(x0, x1, u.y), mix(x2, x3, u.y), u.z);
+}
+
+fn fbm(p: vec3<f32>) -> f32 {
+    var sum = 0.;
+    var weight = 0.5;
+    var point = p;
+    for (var octave = 0; octave < 6; octave++) {
+        sum += weight * perlin(point);
+        weight *= 0.5;
+        point *= 2.;
+    }
+    return sum;
+}
+
+fn turbulence(p: vec3<f32>) -> f32 {
+    var sum = 0.;
+    var weight = 1.;
+    var point = p;
+    for (var octave = 0; octave < 7; octave++) {
+        sum += weight * abs(perlin(point));
+        weight *= 0.5;
+        point *= 2.;
+    }
+    return sum;
+}
+
+fn albedo(material: Material, p: vec3<f32>) -> vec3<f32> {
+    let q = p * material.scale;
+    var blend = 0.;
+    switch material.texture {
+        case 1u: {
+            let sines = sin(q.x) * sin(q.y) * sin(q.z);
+            blend = select(0., 1., sines < 0.);
+        }
+        case 2u: {
+            blend = 0.5 * (1. + fbm(q));
+        }
+        case 3u: {
+            blend = 0.5 * (1. + sin(q.z + material.turbulence * turbulence(q)));
+        }
+        case 4u: {
+            let rings = length(q.xz) + material.turbulence * turbulence(q);
+            blend = rings - floor(rings);
+        }
+        default: {}
+    }
+    return mix(material.albedo, material.secondary, clamp(blend, 0., 1.));
+}
+
+// Returns vec3(t, weight of v1, weight of v2), or -1 when the ray misses
+fn triangle_hit(ray: Ray, triangle_id: u32) -> vec3<f32> {
     let kEpsilon = 0.0001;
     let triangle: Triangle = triangles[triangle_id];
     let v0v1 = triangle.v1 - triangle.v0;
     let v0v2 = triangle.v2 - triangle.v0;
     let N = cross(v0v1, v0v2);
-    let area2 = length(N);
+    let area2 = dot(N, N);
 
-    let invalid = vec4<f32>(-1.0);
+    let invalid = vec3<f32>(-1.0);
     if abs(dot(N, ray.dir)) < kEpsilon {
         return invalid;
     }
@@ -95,83 +524,589 @@ fn triangle_hit(ray: Ray, triangle_id: u32) -> vec4<f32> {
 
     let edge2 = triangle.v0 - triangle.v2;
     let vp2 = P - triangle.v2;
-    C = cross(edge2, vp2);
-    if dot(N, C) < 0.0 {
+    let C2 = cross(edge2, vp2);
+    if dot(N, C2) < 0.0 {
         return invalid;
     }
 
-    return vec4<f32>(t, N.x, N.y, N.z);
+    return vec3<f32>(t, dot(N, C2) / area2, dot(N, cross(edge0, vp0)) / area2);
+}
+
+fn no_hit() -> Hit {
+    var hit: Hit;
+    hit.t = -1.;
+    return hit;
+}
+
+// Flat surfaces are two-sided, their normal always faces the incoming ray
+fn face_forward(hit: Hit, dir: vec3<f32>) -> Hit {
+    var facing = hit;
+    if dot(hit.normal, dir) > 0. {
+        facing.normal = -hit.normal;
+        facing.tangent.w = -hit.tangent.w;
+    }
+    return facing;
+}
+
+fn any_tangent(N: vec3<f32>) -> vec3<f32> {
+    let helper = select(vec3<f32>(1., 0., 0.), vec3<f32>(0., 1., 0.), abs(N.x) > 0.9);
+    return normalize(cross(helper, N));
 }
 
-fn has_hit(ray: Ray) -> array<vec3<f32>, 2> {
+// The ray as seen by an object moving at `velocity`, which stays where it is at time 0.
+// Moving objects only translate, so hits keep their `t` and normal.
+fn ray_at_rest(ray: Ray, velocity: vec3<f32>) -> Ray {
+    var moved = ray;
+    moved.orig -= velocity * ray.time;
+    return moved;
+}
+
+fn plane_hit(ray: Ray, plane: Plane) -> Hit {
+    var hit = no_hit();
+    let denom = dot(plane.normal, ray.dir);
+    if abs(denom) < 1e-8 {
+        return hit;
+    }
+
+    hit.t = dot(plane.point - ray.orig, plane.normal) / denom;
+    let T = any_tangent(plane.normal);
+    let B = cross(plane.normal, T);
+    let local = ray.orig + hit.t * ray.dir - plane.point;
+    hit.material = plane.material;
+    hit.normal = plane.normal;
+    hit.uv = vec2<f32>(dot(local, T), dot(local, B)) * plane.uv_scale;
+    hit.tangent = vec4<f32>(T, 1.);
+    return face_forward(hit, ray.dir);
+}
+
+fn quad_hit(ray: Ray, quad: Quad) -> Hit {
+    var hit = no_hit();
+    let n = cross(quad.u, quad.v);
+    let denom = dot(n, ray.dir);
+    if abs(denom) < 1e-8 {
+        return hit;
+    }
+
+    let t = dot(quad.corner - ray.orig, n) / denom;
+    let planar = ray.orig + t * ray.dir - quad.corner;
+    let w = n / dot(n, n);
+    let alpha = dot(w, cross(planar, quad.v));
+    let beta = dot(w, cross(quad.u, planar));
+    if alpha < 0. || alpha > 1. || beta < 0. || beta > 1. {
+        return hit;
+    }
+
+    hit.t = t;
+    hit.material = quad.material;
+    hit.normal = normalize(n);
+    hit.uv = vec2<f32>(alpha, beta);
+    hit.tangent = vec4<f32>(normalize(quad.u), 1.);
+    return face_forward(hit, ray.dir);
+}
+
+fn disk_hit(ray: Ray, disk: Disk) -> Hit {
+    var hit = no_hit();
+    let denom = dot(disk.normal, ray.dir);
+    if abs(denom) < 1e-8 {
+        return hit;
+    }
+
+    let t = dot(disk.center - ray.orig, disk.normal) / denom;
+    let local = ray.orig + t * ray.dir - disk.center;
+    if dot(local, local) > disk.radius * disk.radius {
+        return hit;
+    }
+
+    let T = any_tangent(disk.normal);
+    let B = cross(disk.normal, T);
+    hit.t = t;
+    hit.material = disk.material;
+    hit.normal = disk.normal;
+    hit.uv = 0.5 + 0.5 * vec2<f32>(dot(local, T), dot(local, B)) / disk.radius;
+    hit.tangent = vec4<f32>(T, 1.);
+    return face_forward(hit, ray.dir);
+}
+
+fn cuboid_hit(ray: Ray, cuboid: Cuboid) -> Hit {
+    var hit = no_hit();
+    if any(cuboid.half_size <= vec3<f32>(0.)) {
+        return hit;
+    }
+
+    // Slab test in the box's own frame, multiplying from the left applies the inverse rotation
+    let axes = mat3x3<f32>(cuboid.axis_x, cuboid.axis_y, cross(cuboid.axis_x, cuboid.axis_y));
+    let origin = (ray.orig - cuboid.center) * axes;
+    let dir = ray.dir * axes;
+    let safe_dir = select(dir, vec3<f32>(1e-12), abs(dir) < vec3<f32>(1e-12));
+    let t0 = (-cuboid.half_size - origin) / safe_dir;
+    let t1 = (cuboid.half_size - origin) / safe_dir;
+    let t_min = min(t0, t1);
+    let t_max = max(t0, t1);
+    let t_near = max(max(t_min.x, t_min.y), t_min.z);
+    let t_far = min(min(t_max.x, t_max.y), t_max.z);
+    if t_near > t_far || t_far <= MIN_T {
+        return hit;
+    }
+
+    let t = select(t_near, t_far, t_near <= MIN_T);
+    let p = origin + t * dir;
+    let relative = abs(p) / cuboid.half_size;
+    var n: vec3<f32>;
+    var T: vec3<f32>;
+    var V: vec3<f32>;
+    var uv: vec2<f32>;
+    if relative.x >= relative.y && relative.x >= relative.z {
+        n = vec3<f32>(sign(p.x), 0., 0.);
+        T = vec3<f32>(0., 0., 1.);
+        V = vec3<f32>(0., 1., 0.);
+        uv = vec2<f32>(p.z, p.y) / cuboid.half_size.zy;
+    }
+    else if relative.y >= relative.z {
+        n = vec3<f32>(0., sign(p.y), 0.);
+        T = vec3<f32>(1., 0., 0.);
+        V = vec3<f32>(0., 0., 1.);
+        uv = vec2<f32>(p.x, p.z) / cuboid.half_size.xz;
+    }
+    else {
+        n = vec3<f32>(0., 0., sign(p.z));
+        T = vec3<f32>(1., 0., 0.);
+        V = vec3<f32>(0., 1., 0.);
+        uv = vec2<f32>(p.x, p.y) / cuboid.half_size.xy;
+    }
+
+    hit.t = t;
+    hit.material = cuboid.material;
+    hit.normal = axes * n;
+    hit.uv = 0.5 + 0.5 * uv;
+    hit.tangent = vec4<f32>(axes * T, select(-1., 1., dot(cross(n, T), V) > 0.));
+    return hit;
+}
+
+fn cylinder_hit(ray: Ray, cylinder: Cylinder) -> Hit {
+    var hit = no_hit();
+    if cylinder.radius <= 0. {
+        return hit;
+    }
+
+    let axis = cylinder.axis;
+    let T0 = any_tangent(axis);
+    let B0 = cross(axis, T0);
+    let oc = ray.orig - cylinder.base;
+    let d_axial = dot(ray.dir, axis);
+    let oc_axial = dot(oc, axis);
+    let d_perp = ray.dir - d_axial * axis;
+    let oc_perp = oc - oc_axial * axis;
+    let r2 = cylinder.radius * cylinder.radius;
+
+    // Side of the cylinder
+    let a = dot(d_perp, d_perp);
+    let half_b = dot(oc_perp, d_perp);
+    let c = dot(oc_perp, oc_perp) - r2;
+    let discr = half_b * half_b - a * c;
+    if a > 1e-12 && discr > 0. {
+        let roots = array<f32, 2>((-half_b - sqrt(discr)) / a, (-half_b + sqrt(discr)) / a);
+        for (var i = 0; i < 2; i++) {
+            let t = roots[i];
+            let h = oc_axial + t * d_axial;
+            if t > MIN_T && h >= 0. && h <= cylinder.height {
+                let N = normalize(oc_perp + t * d_perp);
+                hit.t = t;
+                hit.normal = N;
+                hit.uv = vec2<f32>((atan2(dot(N, B0), dot(N, T0)) + PI) / (2. * PI), h / cylinder.height);
+                hit.tangent = vec4<f32>(cross(axis, N), 1.);
+                break;
+            }
+        }
+    }
+
+    // Caps, the bottom one faces against the axis
+    if abs(d_axial) > 1e-8 {
+        for (var cap = 0; cap < 2; cap++) {
+            let h = f32(cap) * cylinder.height;
+            let t = (h - oc_axial) / d_axial;
+            let p = oc_perp + t * d_perp;
+            if t > MIN_T && dot(p, p) <= r2 && (hit.t < 0. || t < hit.t) {
+                let side = select(-1., 1., cap == 1);
+                hit.t = t;
+                hit.normal = side * axis;
+                hit.uv = 0.5 + 0.5 * vec2<f32>(dot(p, T0), dot(p, B0)) / cylinder.radius;
+                hit.tangent = vec4<f32>(T0, side);
+            }
+        }
+    }
+
+    hit.material = cylinder.material;
+    return hit;
+}
+
+fn sdf_shape(sdf: Sdf, p: vec3<f32>, time: f32) -> f32 {
+    let local = p - sdf.center - sdf.velocity * time;
+    switch sdf.kind {
+        case 1u: {
+            return length(local) - sdf.params.x;
+        }
+        case 2u: {
+            let q = vec2<f32>(length(local.xz) - sdf.params.x, local.y);
+            return length(q) - sdf.params.y;
+        }
+        default: {
+            let q = abs(local) - (sdf.params - vec3<f32>(sdf.rounding));
+            return length(max(q, vec3<f32>(0.))) + min(max(q.x, max(q.y, q.z)), 0.) - sdf.rounding;
+        }
+    }
+}
+
+// Distance to the combined field and the material of the shape closest to `p`, at `time` of the frame
+fn sdf_scene(p: vec3<f32>, time: f32) -> vec2<f32> {
+    var distance = f32(100000000);
+    var material = 0u;
+    for (var i: u32 = 0u; i < arrayLength(&sdfs); i++) {
+        let sdf = sdfs[i];
+        if sdf.kind == 0u {
+            continue;
+        }
+        let d = sdf_shape(sdf, p, time);
+        // A smooth union blending over no distance is a plain one, and would divide by zero below
+        if i == 0u || sdf.op == 0u || (sdf.op == 2u && sdf.blend <= 0.) {
+            if d < distance {
+                material = sdf.material;
+            }
+            distance = min(distance, d);
+        }
+        else if sdf.op == 1u {
+            distance = max(distance, -d);
+        }
+        else {
+            let h = clamp(0.5 + 0.5 * (d - distance) / sdf.blend, 0., 1.);
+            if h < 0.5 {
+                material = sdf.material;
+            }
+            distance = mix(d, distance, h) - sdf.blend * h * (1. - h);
+        }
+    }
+    return vec2<f32>(distance, f32(material));
+}
+
+// Sphere tracing up to `max_t`, which is the closest hit found among the other objects
+fn sdf_hit(ray: Ray, max_t: f32) -> Hit {
+    var hit = no_hit();
+    if arrayLength(&sdfs) == 1u && sdfs[0].kind == 0u {
+        return hit;
+    }
+
+    let speed = length(ray.dir);
+    let dir = ray.dir / speed;
+    var distance = MIN_T * speed;
+    let max_distance = min(max_t * speed, 1000.);
+    for (var step = 0; step < 256 && distance < max_distance; step++) {
+        let p = ray.orig + dir * distance;
+        let d = sdf_scene(p, ray.time).x;
+        if abs(d) < 0.0001 * distance {
+            let e = vec2<f32>(1., -1.) * 0.0005 * distance;
+            let N = normalize(
+                e.xyy * sdf_scene(p + e.xyy, ray.time).x +
+                e.yyx * sdf_scene(p + e.yyx, ray.time).x +
+                e.yxy * sdf_scene(p + e.yxy, ray.time).x +
+                e.xxx * sdf_scene(p + e.xxx, ray.time).x
+            );
+            hit.t = distance / speed;
+            hit.material = u32(sdf_scene(p, ray.time).y);
+            hit.normal = N;
+            hit.uv = p.xz;
+            hit.tangent = vec4<f32>(any_tangent(N), 1.);
+            return hit;
+        }
+        distance += abs(d);
+    }
+    return hit;
+}
+
+fn empty_interval() -> Interval {
+    var interval: Interval;
+    interval.t_in = 1.;
+    interval.t_out = -1.;
+    return interval;
+}
+
+fn operand_interval(ray: Ray, operand: CsgOperand) -> Interval {
+    var interval = empty_interval();
+    let axes = mat3x3<f32>(operand.axis_x, operand.axis_y, cross(operand.axis_x, operand.axis_y));
+    let origin = (ray.orig - operand.center) * axes;
+    let dir = ray.dir * axes;
+
+    if operand.kind == 1u {
+        let a = dot(dir, dir);
+        let half_b = dot(origin, dir);
+        let c = dot(origin, origin) - operand.size.x * operand.size.x;
+        let discr = half_b * half_b - a * c;
+        if discr > 0. && operand.size.x > 0. {
+            interval.t_in = (-half_b - sqrt(discr)) / a;
+            interval.t_out = (-half_b + sqrt(discr)) / a;
+            interval.n_in = axes * normalize(origin + interval.t_in * dir);
+            interval.n_out = axes * normalize(origin + interval.t_out * dir);
+        }
+    }
+    else if operand.kind == 2u {
+        let safe_dir = select(dir, vec3<f32>(1e-12), abs(dir) < vec3<f32>(1e-12));
+        let t0 = (-operand.size - origin) / safe_dir;
+        let t1 = (operand.size - origin) / safe_dir;
+        let t_min = min(t0, t1);
+        let t_max = max(t0, t1);
+        interval.t_in = max(max(t_min.x, t_min.y), t_min.z);
+        interval.t_out = min(min(t_max.x, t_max.y), t_max.z);
+        // The face that was crossed last on the way in and first on the way out
+        let in_axis = select(select(vec3<f32>(0., 0., 1.), vec3<f32>(0., 1., 0.), t_min.y == interval.t_in), vec3<f32>(1., 0., 0.), t_min.x == interval.t_in);
+        let out_axis = select(select(vec3<f32>(0., 0., 1.), vec3<f32>(0., 1., 0.), t_max.y == interval.t_out), vec3<f32>(1., 0., 0.), t_max.x == interval.t_out);
+        interval.n_in = axes * (-sign(dir) * in_axis);
+        interval.n_out = axes * (sign(dir) * out_axis);
+    }
+    else if operand.kind == 3u {
+        // Infinite cylinder around the local y axis clipped by the slab between the caps
+        let radius = operand.size.x;
+        let half_height = operand.size.y / 2.;
+        let a = dot(dir.xz, dir.xz);
+        let half_b = dot(origin.xz, dir.xz);
+        let c = dot(origin.xz, origin.xz) - radius * radius;
+        let discr = half_b * half_b - a * c;
+        var side = empty_interval();
+        if a > 1e-12 && discr > 0. {
+            side.t_in = (-half_b - sqrt(discr)) / a;
+            side.t_out = (-half_b + sqrt(discr)) / a;
+            let p_in = origin.xz + side.t_in * dir.xz;
+            let p_out = origin.xz + side.t_out * dir.xz;
+            side.n_in = axes * normalize(vec3<f32>(p_in.x, 0., p_in.y));
+            side.n_out = axes * normalize(vec3<f32>(p_out.x, 0., p_out.y));
+        }
+        else if c < 0. {
+            // Parallel to the axis and inside the cylinder
+            side.t_in = -1e30;
+            side.t_out = 1e30;
+        }
+
+        let safe_dir = select(dir.y, 1e-12, abs(dir.y) < 1e-12);
+        let t0 = (-half_height - origin.y) / safe_dir;
+        let t1 = (half_height - origin.y) / safe_dir;
+        let cap_in = min(t0, t1);
+        let cap_out = max(t0, t1);
+        let cap_normal = axes * vec3<f32>(0., sign(dir.y), 0.);
+
+        if radius > 0. && half_height > 0. {
+            interval = side;
+            if cap_in > side.t_in {
+                interval.t_in = cap_in;
+                interval.n_in = -cap_normal;
+            }
+            if cap_out < side.t_out {
+                interval.t_out = cap_out;
+                interval.n_out = cap_normal;
+            }
+        }
+    }
+    return interval;
+}
+
+fn inside_interval(interval: Interval, t: f32) -> bool {
+    return interval.t_in < t && t < interval.t_out;
+}
+
+fn inside_csg(op: u32, a: Interval, b: Interval, t: f32) -> bool {
+    let in_a = inside_interval(a, t);
+    let in_b = inside_interval(b, t);
+    switch op {
+        case 0u: { return in_a || in_b; }
+        case 1u: { return in_a && in_b; }
+        default: { return in_a && !in_b; }
+    }
+}
+
+fn csg_hit(ray: Ray, csg: Csg) -> Hit {
+    var hit = no_hit();
+    let a = operand_interval(ray, csg.a);
+    let b = operand_interval(ray, csg.b);
+
+    // The surface of the result is where crossing an operand's boundary changes the membership
+    let boundaries = array<f32, 4>(a.t_in, a.t_out, b.t_in, b.t_out);
+    let normals = array<vec3<f32>, 4>(a.n_in, a.n_out, b.n_in, b.n_out);
+    for (var i = 0; i < 4; i++) {
+        let t = boundaries[i];
+        if (i < 2 && a.t_in > a.t_out) || (i >= 2 && b.t_in > b.t_out) {
+            continue;
+        }
+        let eps = 0.0001 * max(1., abs(t));
+        if t > MIN_T && (hit.t < 0. || t < hit.t) && inside_csg(csg.op, a, b, t - eps) != inside_csg(csg.op, a, b, t + eps) {
+            hit.t = t;
+            // Carved surfaces face into the removed operand
+            hit.normal = select(normals[i], -normals[i], csg.op == 2u && i >= 2);
+        }
+    }
+
+    hit.material = csg.material;
+    hit.tangent = vec4<f32>(any_tangent(hit.normal), 1.);
+    return hit;
+}
+
+fn has_hit(ray: Ray) -> Hit {
     let init_max_t = f32(100000000);
-    let min_t: f32 = 0.001;
-    var max_t: f32 = init_max_t;
-    var ball_index: i32;
 
-    var response: vec3<f32> = vec3<f32>(init_max_t, -1., -1.); // vec3<f32>(t, material, :unused_variable_space:)
-    var N: vec3<f32>;
-    let ok = arrayLength(&balls);
+    var hit: Hit;
+    hit.t = init_max_t;
 
     for (var i: i32 = 0; i < i32(arrayLength(&balls)); i = i + 1){
         let ball = balls[i];
-        let oc: vec3<f32> = ray.orig - ball.center;
+        let center = ball.center + ball.velocity * ray.time;
+        let oc: vec3<f32> = ray.orig - center;
         let a = dot(ray.dir, ray.dir);
         let half_b = dot(oc, ray.dir);
         let c = dot(oc, oc) - ball.radius * ball.radius;
         let discr: f32 = half_b * half_b - a * c;
         if discr > 0.0 {
-
             var solution = (-half_b - sqrt(discr)) / a;
-            if solution > min_t && solution < response.x {
-                response.x = solution;
-                response.y = f32(ball.material);
-                N = normalize(ray.orig + solution * ray.dir - ball.center);
+            if solution <= MIN_T {
+                solution = (-half_b + sqrt(discr)) / a;
             }
-
-            solution = (-half_b + sqrt(discr)) / a;
-            if solution > min_t && solution < response.x {
-                response.x = solution;
-                response.y = f32(ball.material);
-                N = normalize(ray.orig + solution * ray.dir - ball.center);
+            if solution > MIN_T && solution < hit.t {
+                let N = normalize(ray.orig + solution * ray.dir - center);
+                hit.t = solution;
+                hit.material = ball.material;
+                hit.object = OBJECT_BALL;
+                hit.primitive = u32(i);
+                hit.normal = N;
+                // Spherical coordinates, v grows from the bottom pole to the top one
+                hit.uv = vec2<f32>((atan2(-N.z, N.x) + PI) / (2. * PI), acos(-N.y) / PI);
+                let tangent = vec3<f32>(N.z, 0., -N.x);
+                if dot(tangent, tangent) > 0. {
+                    hit.tangent = vec4<f32>(normalize(tangent), 1.);
+                }
+                else {
+                    hit.tangent = vec4<f32>(1., 0., 0., 1.);
+                }
             }
-
         }
     }
 
-    for(var i: i32 = 0; i < i32(arrayLength(&triangles)); i = i + 1){ 
+    for(var i: i32 = 0; i < i32(arrayLength(&triangles)); i = i + 1){
         let triangle_index = u32(i);
-        let triangle_hit: vec4<f32> = triangle_hit(ray, triangle_index);
+        let triangle_hit: vec3<f32> = triangle_hit(ray, triangle_index);
         let t = triangle_hit.x;
-        let N = vec3<f32>(triangle_hit.y, triangle_hit.z, triangle_hit.w);
-        if all(triangle_hit != vec4<f32>(-1.0)) {
-            if t > min_t && t < response.x {
-                response.x = t;
-                response.y = 0f;
+        if t > MIN_T && t < hit.t {
+            let triangle = triangles[triangle_index];
+            let weights = vec3<f32>(1. - triangle_hit.y - triangle_hit.z, triangle_hit.y, triangle_hit.z);
+            var N = normalize(cross(triangle.v1 - triangle.v0, triangle.v2 - triangle.v0));
+            var tangent = triangle.tangent;
+            if dot(N, ray.dir) > 0. {
+                N = -N;
+                tangent.w = -tangent.w;
             }
+            hit.t = t;
+            hit.material = triangle.material;
+            hit.normal = N;
+            hit.uv = triangle.uv0 * weights.x + triangle.uv1 * weights.y + triangle.uv2 * weights.z;
+            hit.tangent = tangent;
+            hit.object = OBJECT_TRIANGLE;
+            hit.primitive = triangle_index;
         }
     }
-    if response.x == init_max_t {
-        response.x = -1.;
+    for (var i: u32 = 0u; i < arrayLength(&planes); i++) {
+        let candidate = plane_hit(ray_at_rest(ray, planes[i].velocity), planes[i]);
+        if candidate.t > MIN_T && candidate.t < hit.t {
+            hit = candidate;
+            hit.object = OBJECT_PLANE;
+            hit.primitive = i;
+        }
     }
-    return array(response, N);
-}
 
-fn random_vec3(seed: f32, N: vec3<f32>) -> vec3<f32> {
-    var unit: vec3<f32>;
-    var current_seed = seed;
-    loop { 
-        unit = vec3<f32>(prng(current_seed), prng(current_seed + 1.), prng(current_seed + 2.));
-        if dot(unit, unit) >= 1. {
-            current_seed += 3.0;
-            continue;
+    for (var i: u32 = 0u; i < arrayLength(&quads); i++) {
+        let candidate = quad_hit(ray_at_rest(ray, quads[i].velocity), quads[i]);
+        if candidate.t > MIN_T && candidate.t < hit.t {
+            hit = candidate;
+            hit.object = OBJECT_QUAD;
+            hit.primitive = i;
         }
-        else {
-            break;
+    }
+
+    for (var i: u32 = 0u; i < arrayLength(&disks); i++) {
+        let candidate = disk_hit(ray_at_rest(ray, disks[i].velocity), disks[i]);
+        if candidate.t > MIN_T && candidate.t < hit.t {
+            hit = candidate;
+            hit.object = OBJECT_DISK;
+            hit.primitive = i;
         }
     }
-    
-    let random_vector = normalize(unit);
+
+    for (var i: u32 = 0u; i < arrayLength(&cuboids); i++) {
+        let candidate = cuboid_hit(ray_at_rest(ray, cuboids[i].velocity), cuboids[i]);
+        if candidate.t > MIN_T && candidate.t < hit.t {
+            hit = candidate;
+            hit.object = OBJECT_CUBOID;
+            hit.primitive = i;
+        }
+    }
+
+    for (var i: u32 = 0u; i < arrayLength(&cylinders); i++) {
+        let candidate = cylinder_hit(ray_at_rest(ray, cylinders[i].velocity), cylinders[i]);
+        if candidate.t > MIN_T && candidate.t < hit.t {
+            hit = candidate;
+            hit.object = OBJECT_CYLINDER;
+            hit.primitive = i;
+        }
+    }
+
+    for (var i: u32 = 0u; i < arrayLength(&csgs); i++) {
+        let candidate = csg_hit(ray, csgs[i]);
+        if candidate.t > MIN_T && candidate.t < hit.t {
+            hit = candidate;
+            hit.object = OBJECT_CSG;
+            hit.primitive = i;
+        }
+    }
+
+    let sdf_candidate = sdf_hit(ray, hit.t);
+    if sdf_candidate.t > MIN_T && sdf_candidate.t < hit.t {
+        // The SDF shapes are fused into a single field
+        hit = sdf_candidate;
+        hit.object = OBJECT_SDF;
+        hit.primitive = 0u;
+    }
+
+    if hit.t == init_max_t {
+        hit.t = -1.;
+    }
+    return hit;
+}
+
+fn sample_map(layer: i32, uv: vec2<f32>) -> vec4<f32> {
+    // Images are stored top row first while v grows upwards
+    return textureSampleLevel(maps, maps_sampler, vec2<f32>(uv.x, 1. - uv.y), layer, 0.);
+}
+
+fn shading_normal(hit: Hit, material: Material) -> vec3<f32> {
+    var N = hit.normal;
+    let T = normalize(hit.tangent.xyz - N * dot(N, hit.tangent.xyz));
+    let B = cross(N, T) * hit.tangent.w;
+
+    if material.normal_map >= 0 {
+        let local = sample_map(material.normal_map, hit.uv).xyz * 2. - 1.;
+        N = normalize(T * local.x + B * local.y + N * local.z);
+    }
+
+    if material.bump_map >= 0 {
+        // Height differences to the neighbouring texels tilt the normal away from the slope
+        let texel = 1. / vec2<f32>(textureDimensions(maps));
+        let height = sample_map(material.bump_map, hit.uv).r;
+        let du = sample_map(material.bump_map, hit.uv + vec2<f32>(texel.x, 0.)).r - height;
+        let dv = sample_map(material.bump_map, hit.uv + vec2<f32>(0., texel.y)).r - height;
+        N = normalize(N - material.bump_strength * (du * T + dv * B));
+    }
+
+    return N;
+}
+
+// Uniformly distributed direction in the hemisphere around `N`
+fn random_vec3(N: vec3<f32>, u: vec2<f32>) -> vec3<f32> {
+    let z = 1. - 2. * u.x;
+    let r = sqrt(max(0., 1. - z * z));
+    let phi = 2. * PI * u.y;
+    let random_vector = vec3<f32>(r * cos(phi), r * sin(phi), z);
     if dot(random_vector, N) > 0.0 {
         return random_vector;
     }
@@ -180,27 +1115,147 @@ fn random_vec3(seed: f32, N: vec3<f32>) -> vec3<f32> {
     }
 }
 
+// Trilinear interpolation, R32Float textures can't be filtered by a sampler
+fn grid_density(uvw: vec3<f32>) -> f32 {
+    let size = vec3<i32>(textureDimensions(density_grid));
+    let position = clamp(uvw, vec3<f32>(0.), vec3<f32>(1.)) * vec3<f32>(size) - 0.5;
+    let base = floor(position);
+    let f = position - base;
+
+    var density = 0.;
+    for (var i: u32 = 0u; i < 8u; i++) {
+        let corner = vec3<f32>(f32(i & 1u), f32((i >> 1u) & 1u), f32((i >> 2u) & 1u));
+        let texel = clamp(vec3<i32>(base + corner), vec3<i32>(0), size - 1);
+        let weight = mix(1. - f, f, corner);
+        density += weight.x * weight.y * weight.z * textureLoad(density_grid, texel, 0).r;
+    }
+    return density;
+}
+
+fn volume_density(volume: Volume, p: vec3<f32>) -> f32 {
+    if volume.heterogeneous == 0u {
+        return volume.density;
+    }
+    // The grid spans the bounding box, a ball's size only holds the radius
+    let extent = select(volume.size, vec3<f32>(volume.size.x), volume.shape == 1u);
+    return volume.density * grid_density(0.5 + 0.5 * (p - volume.center) / extent);
+}
+
+// Delta tracking: the closest real collision among all the media before `t_surface`.
+// Each volume's density is its majorant, so homogeneous media accept the first tentative collision.
+fn volume_scatter(ray: Ray, t_surface: f32) -> Scatter {
+    var scatter: Scatter;
+    scatter.t = -1.;
+    let t_max = select(1e30, t_surface, t_surface > 0.);
+    let speed = length(ray.dir);
 
-fn ray_color(ray: Ray, seed: vec4<f32>) -> vec3<f32> {
+    for (var i: u32 = 0u; i < arrayLength(&volumes); i++) {
+        var volume = volumes[i];
+        volume.center += volume.velocity * ray.time;
+        if volume.shape == 0u || volume.density <= 0. {
+            continue;
+        }
+
+        var bounds: CsgOperand;
+        bounds.center = volume.center;
+        bounds.kind = volume.shape;
+        bounds.size = volume.size;
+        bounds.axis_x = vec3<f32>(1., 0., 0.);
+        bounds.axis_y = vec3<f32>(0., 1., 0.);
+        let interval = operand_interval(ray, bounds);
+        let t_in = max(interval.t_in, 0.);
+        let t_out = min(interval.t_out, t_max);
+        if t_in >= t_out {
+            continue;
+        }
+
+        var t = t_in;
+        for (var step = 0; step < 256; step++) {
+            // Exponentially distributed distance, converted to the parametrisation of the ray
+            t -= log(1. - random()) / (volume.density * speed);
+            if t >= t_out || (scatter.t > 0. && t >= scatter.t) {
+                break;
+            }
+            let density = volume_density(volume, ray.orig + ray.dir * t);
+            if random() * volume.density < density {
+                scatter.t = t;
+                scatter.albedo = volume.albedo;
+                scatter.anisotropy = volume.anisotropy;
+                break;
+            }
+        }
+    }
+    return scatter;
+}
+
+fn sample_henyey_greenstein(dir: vec3<f32>, g: f32, u: vec2<f32>) -> vec3<f32> {
+    let u1 = u.x;
+    let u2 = u.y;
+    var cos_theta: f32;
+    if abs(g) < 0.001 {
+        cos_theta = 1. - 2. * u1;
+    }
+    else {
+        let s = (1. - g * g) / (1. - g + 2. * g * u1);
+        cos_theta = (1. + g * g - s * s) / (2. * g);
+    }
+    let sin_theta = sqrt(max(0., 1. - cos_theta * cos_theta));
+    let phi = 2. * PI * u2;
+    let T = any_tangent(dir);
+    let B = cross(dir, T);
+    return normalize(sin_theta * cos(phi) * T + sin_theta * sin(phi) * B + cos_theta * dir);
+}
+
+
+// Russian roulette: a path survives with probability proportional to its throughput and is reweighted by
+// the inverse of it, which keeps the estimate unbiased. Returns the new throughput, 0 for terminated paths
+fn roulette(throughput: vec3<f32>, bounce: u32) -> vec3<f32> {
+    if settings.roulette_threshold <= 0. || bounce < settings.roulette_depth {
+        return throughput;
+    }
+    let survival = min(max(throughput.x, max(throughput.y, throughput.z)) / settings.roulette_threshold, 1.);
+    if random() >= survival {
+        return vec3<f32>(0.);
+    }
+    return throughput / survival;
+}
+
+fn ray_color(ray: Ray) -> vec3<f32> {
     var output_color: vec3<f32> = vec3<f32>(1.);
-    var depth: i32 = 5;
+    var depth: i32 = i32(settings.max_depth);
 
     var current_ray = ray;
     while depth > 0 {
-        let hit_response = has_hit(current_ray);
-        let t = hit_response[0].x;
+        let bounce = settings.max_depth - u32(depth);
+        rng_start_bounce(bounce);
+        // Drawn before the variable number of draws of delta tracking, so it's always the same dimensions
+        let direction_sample = sample_2d();
+        let hit = has_hit(current_ray);
+        let t = hit.t;
+
+        let scatter = volume_scatter(current_ray, t);
+        if scatter.t > 0. {
+            current_ray.orig = current_ray.orig + current_ray.dir * scatter.t;
+            current_ray.dir = sample_henyey_greenstein(normalize(current_ray.dir), scatter.anisotropy, direction_sample);
+            output_color = roulette(output_color * scatter.albedo, bounce);
+            if all(output_color == vec3<f32>(0.)) {
+                return output_color;
+            }
+            depth -= 1;
+            continue;
+        }
 
         if t > 0.0 {
             let hit_point = current_ray.orig + current_ray.dir * t;
-            let N = hit_response[1];
-            let material = u32(hit_response[0].y);
+            let material = materials[hit.material];
+            let N = shading_normal(hit, material);
 
             var new_target: vec3<f32>;
-            if material == 0u {
-                new_target = hit_point + N + random_vec3(seed.x, N);
+            if material.kind == 0u {
+                new_target = hit_point + N + random_vec3(N, direction_sample);
             }
             else {
-                // let N_offset = normalize(50.0 * N + random_vec3(seed.x, N));
+                // let N_offset = normalize(50.0 * N + random_vec3(N, direction_sample));
                 let N_offset = N;
                 new_target = hit_point + current_ray.dir - 2.0*dot(N_offset, current_ray.dir) * N_offset;
             }
@@ -209,10 +1264,14 @@ fn ray_color(ray: Ray, seed: vec4<f32>) -> vec3<f32> {
             new_ray.screen_y = ray.screen_y;
             new_ray.dir = normalize(new_target - hit_point);
             new_ray.orig = hit_point;
+            new_ray.time = current_ray.time;
 
             current_ray = new_ray;
 
-            output_color *= 0.7;
+            output_color = roulette(output_color * albedo(material, hit_point), bounce);
+            if all(output_color == vec3<f32>(0.)) {
+                return output_color;
+            }
         }
         else if t == -1.0 {
             let unit_direction = normalize(current_ray.dir);
@@ -227,9 +1286,130 @@ fn ray_color(ray: Ray, seed: vec4<f32>) -> vec3<f32> {
         depth -= 1;
     }
 
-    return output_color;
+    // The path ran out of bounces before reaching the sky, which is the only light
+    return vec3<f32>(0.);
+}
 
+const FILTER_BOX: u32 = 0u;
+const FILTER_TENT: u32 = 1u;
+const FILTER_GAUSSIAN: u32 = 2u;
+const FILTER_MITCHELL: u32 = 3u;
 
+// Mitchell-Netravali with B = C = 1/3, defined over [-2, 2]
+fn mitchell(x: f32) -> f32 {
+    let B = 1. / 3.;
+    let C = 1. / 3.;
+    let ax = abs(x);
+    if ax < 1. {
+        return ((12. - 9. * B - 6. * C) * ax * ax * ax + (-18. + 12. * B + 6. * C) * ax * ax + (6. - 2. * B)) / 6.;
+    }
+    if ax < 2. {
+        return ((-B - 6. * C) * ax * ax * ax + (6. * B + 30. * C) * ax * ax + (-12. * B - 48. * C) * ax + (8. * B + 24. * C)) / 6.;
+    }
+    return 0.;
+}
+
+// One dimension of the separable filter, `x` is the offset from the pixel's center in pixels
+fn filter_1d(x: f32) -> f32 {
+    let radius = settings.filter_radius;
+    switch settings.pixel_filter {
+        case FILTER_TENT: {
+            return max(radius - abs(x), 0.);
+        }
+        case FILTER_GAUSSIAN: {
+            // Standard deviation of half a pixel, shifted so it reaches 0 at the radius
+            return max(exp(-2. * x * x) - exp(-2. * radius * radius), 0.);
+        }
+        case FILTER_MITCHELL: {
+            return mitchell(2. * x / radius);
+        }
+        default: {
+            return 1.;
+        }
+    }
+}
+
+// Units of the fixed point splat sums per unit of radiance or weight. The scale cancels out of `pixel_mean`.
+// A pixel's sums overflow after about two million splats of unit radiance
+const SPLAT_SCALE: f32 = 1024.;
+// Largest contribution one splat adds, so that a single firefly can't overflow the sums
+const SPLAT_MAX: f32 = 65536.;
+
+// Neighbouring tiles splat into the same pixels. Integer additions give the same sums in any order,
+// which keeps renders with the same seed identical, unlike adding floats
+fn splat_add(index: u32, value: f32) {
+    atomicAdd(&splats[index], i32(round(clamp(value, -SPLAT_MAX, SPLAT_MAX) * SPLAT_SCALE)));
+}
+
+// Adds the sample taken at `position` (in pixels, centers at integer coordinates) to every pixel
+// whose filter footprint covers it
+fn splat(position: vec2<f32>, sample: vec3<f32>) {
+    let height = arrayLength(&splats) / 4u / settings.width;
+    let radius = settings.filter_radius;
+    let low = vec2<i32>(ceil(position - radius));
+    let high = vec2<i32>(floor(position + radius));
+    for (var y = max(low.y, 0); y <= min(high.y, i32(height) - 1); y++) {
+        for (var x = max(low.x, 0); x <= min(high.x, i32(settings.width) - 1); x++) {
+            let offset = position - vec2<f32>(f32(x), f32(y));
+            // The box filter is 1 everywhere, its footprint ends at the radius
+            if abs(offset.x) >= radius || abs(offset.y) >= radius {
+                continue;
+            }
+            let weight = filter_1d(offset.x) * filter_1d(offset.y);
+            if weight == 0. {
+                continue;
+            }
+            let index = 4u * (u32(y) * settings.width + u32(x));
+            splat_add(index, sample.r * weight);
+            splat_add(index + 1u, sample.g * weight);
+            splat_add(index + 2u, sample.b * weight);
+            splat_add(index + 3u, weight);
+        }
+    }
+}
+
+// Ratio of the splatted sums. Mitchell's negative lobes can leave a negative weight, whose sign the ratio keeps
+fn pixel_mean(pixel_index: u32) -> vec3<f32> {
+    let index = 4u * pixel_index;
+    let weight = atomicLoad(&splats[index + 3u]);
+    if weight == 0 {
+        return vec3<f32>(0.);
+    }
+    let color = vec3<f32>(
+        f32(atomicLoad(&splats[index])),
+        f32(atomicLoad(&splats[index + 1u])),
+        f32(atomicLoad(&splats[index + 2u])),
+    );
+    return color / f32(weight);
+}
+
+fn write_aovs(ray_index: u32, ray: Ray) {
+    let hit = has_hit(ray);
+    if hit.t > 0. {
+        let material = materials[hit.material];
+        let hit_point = ray.orig + ray.dir * hit.t;
+        v_indices[ray_index].depth = hit.t * length(ray.dir);
+        v_indices[ray_index].normal = shading_normal(hit, material);
+        v_indices[ray_index].material = hit.material;
+        v_indices[ray_index].albedo = albedo(material, hit_point);
+        v_indices[ray_index].object_id = hit.object;
+        v_indices[ray_index].primitive_id = hit.primitive;
+    }
+    else {
+        v_indices[ray_index].depth = 0.;
+        v_indices[ray_index].normal = vec3<f32>(0.);
+        v_indices[ray_index].material = NO_MATERIAL;
+        v_indices[ray_index].albedo = vec3<f32>(0.);
+        v_indices[ray_index].object_id = OBJECT_NONE;
+        v_indices[ray_index].primitive_id = 0u;
+    }
+}
+
+// Standard error of the pixel's mean luminance relative to the mean itself
+fn relative_error(pixel: Accumulator) -> f32 {
+    let mean = pixel.luminance / pixel.samples;
+    let variance = max(pixel.luminance_sq / pixel.samples - mean * mean, 0.);
+    return sqrt(variance / pixel.samples) / (mean + 0.001);
 }
 
 @compute
@@ -237,25 +1417,52 @@ fn ray_color(ray: Ray, seed: vec4<f32>) -> vec3<f32> {
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>,
         @builtin(num_workgroups) workgroups: vec3<u32>) {
     let ray_index = global_id.x + global_id.y * workgroups.x;
-    let texture_size: vec2<u32> = textureDimensions(noise_texture);
-    let noise_y: u32 = u32(floor(f32(global_id.x) / f32(texture_size.x)));
-    let noise_x: u32 = global_id.x - noise_y * texture_size.x;
-
-    let seed: vec4<f32> = textureLoad(noise_texture, vec2<u32>(global_id.x, global_id.y), 0)*100000f;
+    // Tiles are dispatched with their exact dimensions, this only guards against reading past the rays
+    if ray_index >= arrayLength(&v_indices) {
+        return;
+    }
     var ray: Ray = v_indices[ray_index];
     let RAY_ORIGIN_DIR = ray.dir;
 
     let delta_u: vec3<f32> = pixel_delta_u;
     let delta_v: vec3<f32> = pixel_delta_v;
 
-    var output_color = vec3<f32>(0.);
-    let SAMPLES = 4;
+    let pixel_index = ray.screen_y * settings.width + ray.screen_x;
+    var pixel = accumulation[pixel_index];
+
+    // Written before adaptive sampling may skip the pixel, the collected image needs them every pass
+    write_aovs(ray_index, ray);
+
+    // Adaptive sampling: converged pixels are skipped, noisy ones take up to 4 times the samples
+    var SAMPLES = i32(settings.samples);
+    if settings.adaptive_threshold > 0. && pixel.samples >= f32(settings.adaptive_min_samples) {
+        let error_ratio = relative_error(pixel) / settings.adaptive_threshold;
+        if error_ratio < 1. {
+            v_indices[ray_index].color = pixel_mean(pixel_index);
+            return;
+        }
+        SAMPLES *= i32(ceil(min(error_ratio, 4.)));
+    }
 
     for(var sample_index: i32 = 1; sample_index < SAMPLES + 1; sample_index++) {
-        ray.dir = RAY_ORIGIN_DIR + pixel_delta_u * (prng(seed.x * f32(sample_index)) - 1f) / 2f + pixel_delta_v * (prng(seed.y * f32(sample_index)) - 1f) / 2f;
-        output_color += ray_color(ray, seed * f32(sample_index)) / f32(SAMPLES);
+        // Samples of a pixel are numbered across passes, so every pass continues the same sequence
+        rng_init(vec2<u32>(ray.screen_x, ray.screen_y), settings.pass_index, u32(pixel.samples) + u32(sample_index) - 1u);
+        // The ray passes through the pixel's center, samples are spread over the pixel's area
+        // and splatted into every pixel whose filter reaches them
+        let offset = sample_2d() - 0.5;
+        ray.time = mix(settings.shutter_open, settings.shutter_close, sample_2d().x);
+        ray.dir = RAY_ORIGIN_DIR + pixel_delta_u * offset.x + pixel_delta_v * offset.y;
+        let sample = ray_color(ray);
+        splat(vec2<f32>(f32(ray.screen_x), f32(ray.screen_y)) + offset, sample);
+        let luminance = dot(sample, vec3<f32>(0.2126, 0.7152, 0.0722));
+        pixel.luminance += luminance;
+        pixel.luminance_sq += luminance * luminance;
     }
+    pixel.samples += f32(SAMPLES);
 
-    v_indices[ray_index].color = output_color;
+    // Previous passes left their samples in the accumulation buffer, the ray returns the running mean.
+    // Tiles dispatched later still splat into the pixels at this tile's edges, see `Renderer::resolve`
+    accumulation[pixel_index] = pixel;
+    v_indices[ray_index].color = pixel_mean(pixel_index);
     let s = triangles[0];
 }
\ No newline at end of file
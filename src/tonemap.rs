@@ -0,0 +1,122 @@
+use std::str::FromStr;
+
+
+/// Operators compressing the film's unbounded range into [0, 1] for 8-bit formats.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMapping {
+    /// Values above 1 are clipped.
+    #[default]
+    None,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    /// Polynomial approximation of Blender's AgX by Wrensch.
+    Agx,
+}
+
+impl FromStr for ToneMapping {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "none" => Ok(ToneMapping::None),
+            "reinhard" => Ok(ToneMapping::Reinhard),
+            "aces" => Ok(ToneMapping::Aces),
+            "agx" => Ok(ToneMapping::Agx),
+            _ => Err(format!("Unknown tone mapping {}", name)),
+        }
+    }
+}
+
+/// Turns linear film values into 8-bit sRGB: exposure, tone mapping and the sRGB transfer function.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct OutputTransform {
+    /// In stops, every stop doubles the brightness.
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+}
+
+impl OutputTransform {
+    pub fn apply(&self, color: [f32; 3]) -> [u8; 3] {
+        let scale = self.exposure.exp2();
+        let color = color.map(|c| (c * scale).max(0.));
+
+        let display = match self.tone_mapping {
+            ToneMapping::None => color.map(srgb_oetf),
+            ToneMapping::Reinhard => color.map(|c| srgb_oetf(c / (1. + c))),
+            ToneMapping::Aces => color.map(|c| srgb_oetf(aces(c))),
+            // AgX's curve already produces display encoded values
+            ToneMapping::Agx => agx(color),
+        };
+        display.map(|c| (c.clamp(0., 1.) * 255. + 0.5) as u8)
+    }
+}
+
+/// sRGB opto-electronic transfer function, linear [0, 1] to encoded [0, 1].
+pub fn srgb_oetf(linear: f32) -> f32 {
+    let linear = linear.clamp(0., 1.);
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    }
+}
+
+fn aces(x: f32) -> f32 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+// Columns of the matrices from the reference implementation
+const AGX_INSET: [[f32; 3]; 3] = [
+    [0.84247905, 0.042328242, 0.042375654],
+    [0.0784336, 0.87846863, 0.0784336],
+    [0.079223745, 0.07916613, 0.879143],
+];
+const AGX_OUTSET: [[f32; 3]; 3] = [
+    [1.196879, -0.052896854, -0.052971635],
+    [-0.09802088, 1.1519032, -0.09804345],
+    [-0.09902974, -0.098961174, 1.1510737],
+];
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+fn transform(columns: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|row| columns[0][row] * v[0] + columns[1][row] * v[1] + columns[2][row] * v[2])
+}
+
+fn agx_contrast(x: f32) -> f32 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+}
+
+fn agx(color: [f32; 3]) -> [f32; 3] {
+    let inset = transform(&AGX_INSET, color);
+    let curve = inset.map(|c| {
+        let ev = c.max(f32::MIN_POSITIVE).log2().clamp(AGX_MIN_EV, AGX_MAX_EV);
+        agx_contrast((ev - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV))
+    });
+    transform(&AGX_OUTSET, curve)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_transform() {
+        assert_eq!(srgb_oetf(0.), 0.);
+        assert!((srgb_oetf(1.) - 1.).abs() < 1e-6);
+        assert!((srgb_oetf(0.18) - 0.4613).abs() < 1e-3);
+
+        for tone_mapping in [ToneMapping::None, ToneMapping::Reinhard, ToneMapping::Aces, ToneMapping::Agx] {
+            let transform = OutputTransform { exposure: 0., tone_mapping };
+            assert!(transform.apply([0.; 3]).iter().all(|&c| c <= 1));
+            assert_eq!(transform.apply([1e6; 3]), [255; 3]);
+        }
+
+        let brighter = OutputTransform { exposure: 1., tone_mapping: ToneMapping::None };
+        assert_eq!(brighter.apply([0.5; 3]), [255; 3]);
+    }
+}
@@ -1,28 +1,63 @@
-use ultraviolet::Vec3;
+use ultraviolet::{Vec2, Vec3, Vec4};
 use bytemuck::*;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct Triangle {
     pub v1: Vec3,
-    _pad0: u32,
+    pub material: u32,
     pub v2: Vec3,
-    _pad1: u32,
+    _pad0: u32,
     pub v3: Vec3,
-    _pad2: u32,
+    _pad1: u32,
+    pub tangent: Vec4, // xyz - tangent along increasing u, w - handedness of the bitangent
+    pub uvs: [Vec2; 3],
+    _pad2: [u32; 2],
 }
 
 
 impl Triangle {
     pub fn new(v1: Vec3, v2: Vec3, v3: Vec3) -> Self {
+        let tangent = (v2 - v1).normalized();
         Self {
             v1, v2, v3,
+            material: 0,
+            tangent: Vec4::new(tangent.x, tangent.y, tangent.z, 1.),
+            uvs: Default::default(),
             _pad0: Default::default(),
             _pad1: Default::default(),
             _pad2: Default::default(),
         }
     }
+
+    pub fn with_material(mut self, material: u32) -> Self {
+        self.material = material;
+        self
+    }
+
+    /// Attaches texture coordinates and derives the tangent frame used by normal and bump maps.
+    pub fn with_uvs(mut self, uvs: [Vec2; 3]) -> Self {
+        self.uvs = uvs;
+
+        let e1 = self.v2 - self.v1;
+        let e2 = self.v3 - self.v1;
+        let duv1 = uvs[1] - uvs[0];
+        let duv2 = uvs[2] - uvs[0];
+        let det = duv1.x * duv2.y - duv2.x * duv1.y;
+        if det.abs() < 1e-12 {
+            // Degenerate or missing UVs, keep the edge-aligned tangent from `new`
+            return self;
+        }
+
+        let r = 1. / det;
+        let tangent = ((e1 * duv2.y - e2 * duv1.y) * r).normalized();
+        let bitangent = (e2 * duv1.x - e1 * duv2.x) * r;
+        let normal = e1.cross(e2);
+        let handedness = if normal.cross(tangent).dot(bitangent) < 0. { -1. } else { 1. };
+        self.tangent = Vec4::new(tangent.x, tangent.y, tangent.z, handedness);
+        self
+    }
 }
 
 unsafe impl Pod for Triangle {}
-unsafe impl Zeroable for Triangle {}
\ No newline at end of file
+unsafe impl Zeroable for Triangle {}
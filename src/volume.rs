@@ -0,0 +1,65 @@
+use bytemuck::{Pod, Zeroable};
+use ultraviolet::Vec3;
+
+
+const BALL: u32 = 1;
+const CUBOID: u32 = 2;
+
+/// Homogeneous participating medium filling a ball or an axis aligned box.
+/// `density` is the extinction coefficient per unit of length, `anisotropy` the Henyey-Greenstein `g`
+/// (negative scatters backwards, positive forwards).
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Volume {
+    pub center: Vec3,
+    shape: u32,
+    size: Vec3, // ball - radius in x, box - half size
+    pub density: f32,
+    pub albedo: Vec3,
+    pub anisotropy: f32,
+    heterogeneous: u32,
+    _pad0: [u32; 3],
+    /// Distance covered per frame, blurs the medium when the shutter is open for a while.
+    pub velocity: Vec3,
+    _pad1: u32,
+}
+
+unsafe impl Pod for Volume {}
+unsafe impl Zeroable for Volume {}
+
+impl Volume {
+    pub fn ball(center: Vec3, radius: f32, density: f32, albedo: Vec3, anisotropy: f32) -> Self {
+        Self::new(BALL, center, Vec3::new(radius, 0., 0.), density, albedo, anisotropy)
+    }
+
+    pub fn cuboid(min: Vec3, max: Vec3, density: f32, albedo: Vec3, anisotropy: f32) -> Self {
+        Self::new(CUBOID, (min + max) / 2., (max - min) / 2., density, albedo, anisotropy)
+    }
+
+    /// Modulates the density with `Scene::density_grid`, stretched over the volume's bounding box.
+    /// The density then acts as the majorant for delta tracking.
+    pub fn with_grid(mut self) -> Self {
+        self.heterogeneous = 1;
+        self
+    }
+
+    /// Grows the medium about its center, a grid stretches along.
+    pub fn scale(&mut self, factor: f32) {
+        self.size *= factor;
+    }
+
+    fn new(shape: u32, center: Vec3, size: Vec3, density: f32, albedo: Vec3, anisotropy: f32) -> Self {
+        Self {
+            center,
+            shape,
+            size,
+            density,
+            albedo,
+            anisotropy,
+            heterogeneous: 0,
+            _pad0: Default::default(),
+            velocity: Vec3::zero(),
+            _pad1: 0,
+        }
+    }
+}